@@ -0,0 +1,11 @@
+datetime-format = %-d. %B %Y, %H:%M UTC
+
+digest-subject = Cr8s-Übersicht
+digest-title = Cr8s-Übersicht
+digest-heading = Cr8s-Übersicht
+digest-intro = { $count ->
+    [one] Hier ist die Crate, die zwischen { $since } und { $until } erstellt wurde.
+   *[other] Hier sind die { $count } Crates, die zwischen { $since } und { $until } erstellt wurden.
+}
+digest-footer = © { $year } Erstellt und versendet von der cr8s Rust-App
+digest-unsubscribe = Abbestellen
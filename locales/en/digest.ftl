@@ -0,0 +1,12 @@
+# strftime pattern for dates in emails, month and day names follow the locale.
+datetime-format = %B %-d, %Y %H:%M UTC
+
+digest-subject = Cr8s digest
+digest-title = Cr8s digest
+digest-heading = Cr8s digest
+digest-intro = { $count ->
+    [one] Here is the crate created between { $since } and { $until }.
+   *[other] Here are the { $count } crates created between { $since } and { $until }.
+}
+digest-footer = © { $year } Generated and sent by cr8s rust app
+digest-unsubscribe = Unsubscribe
@@ -0,0 +1,11 @@
+datetime-format = %-d %B %Y, %H:%M UTC
+
+digest-subject = Résumé Cr8s
+digest-title = Résumé Cr8s
+digest-heading = Résumé Cr8s
+digest-intro = { $count ->
+    [one] Voici la crate créée entre le { $since } et le { $until }.
+   *[other] Voici les { $count } crates créées entre le { $since } et le { $until }.
+}
+digest-footer = © { $year } Généré et envoyé par l'application Rust cr8s
+digest-unsubscribe = Se désabonner
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN email varchar(128) UNIQUE;
+ALTER TABLE users ADD COLUMN oidc_subject varchar(255) UNIQUE;
@@ -0,0 +1,9 @@
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL,
+    impersonator_id integer,
+    method varchar(16) NOT NULL,
+    path text NOT NULL,
+    status integer NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE digest_runs (
+    id SERIAL PRIMARY KEY,
+    recipient varchar(128) NOT NULL,
+    since TIMESTAMP NOT NULL,
+    until TIMESTAMP NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX digest_runs_recipient_idx ON digest_runs (recipient, until);
@@ -0,0 +1,12 @@
+CREATE TABLE digest_subscriptions (
+    id SERIAL PRIMARY KEY,
+    user_id integer REFERENCES users(id) ON DELETE CASCADE,
+    rustacean_id integer REFERENCES rustaceans(id) ON DELETE CASCADE,
+    frequency varchar(16) NOT NULL DEFAULT 'daily',
+    owner_id integer REFERENCES rustaceans(id) ON DELETE CASCADE,
+    keyword varchar(64),
+    last_sent_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL,
+    CHECK (num_nonnulls(user_id, rustacean_id) = 1),
+    CHECK (frequency IN ('daily', 'weekly'))
+);
@@ -0,0 +1,10 @@
+CREATE TABLE job_runs (
+    id SERIAL PRIMARY KEY,
+    job varchar(64) NOT NULL,
+    started_at TIMESTAMP DEFAULT NOW() NOT NULL,
+    finished_at TIMESTAMP,
+    succeeded boolean,
+    error text
+);
+
+CREATE INDEX job_runs_job_idx ON job_runs (job, started_at);
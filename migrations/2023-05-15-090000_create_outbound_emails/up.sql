@@ -0,0 +1,18 @@
+CREATE TABLE outbound_emails (
+    id SERIAL PRIMARY KEY,
+    from_address varchar(255) NOT NULL,
+    to_address varchar(255) NOT NULL,
+    subject text NOT NULL,
+    html_body text NOT NULL,
+    text_body text,
+    list_unsubscribe text,
+    status varchar(16) NOT NULL DEFAULT 'pending',
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP DEFAULT NOW() NOT NULL,
+    last_error text,
+    sent_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL,
+    CHECK (status IN ('pending', 'sending', 'sent', 'dead'))
+);
+
+CREATE INDEX outbound_emails_due_idx ON outbound_emails (status, next_attempt_at);
@@ -0,0 +1,2 @@
+ALTER TABLE digest_subscriptions DROP COLUMN locale;
+ALTER TABLE users DROP COLUMN locale;
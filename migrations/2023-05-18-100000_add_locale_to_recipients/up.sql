@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN locale varchar(16);
+ALTER TABLE digest_subscriptions ADD COLUMN locale varchar(16);
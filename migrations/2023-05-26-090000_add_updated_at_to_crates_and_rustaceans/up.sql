@@ -0,0 +1,4 @@
+ALTER TABLE rustaceans ADD COLUMN updated_at TIMESTAMP DEFAULT NOW() NOT NULL;
+ALTER TABLE crates ADD COLUMN updated_at TIMESTAMP DEFAULT NOW() NOT NULL;
+SELECT diesel_manage_updated_at('rustaceans');
+SELECT diesel_manage_updated_at('crates');
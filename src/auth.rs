@@ -3,28 +3,71 @@ use argon2::password_hash::{
 };
 use argon2::{Argon2, PasswordHash};
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use sha2::Sha256;
 
+use crate::jwt::KeyConfig;
 use crate::models::User;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, schemars::JsonSchema)]
 pub struct Credentials {
     pub username: String,
     pub password: String,
 }
 
+#[derive(serde::Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Opaque tokens looked up in Redis on every request.
+    #[default]
+    Session,
+    /// Short-lived signed JWTs verified locally; Redis only holds revocations.
+    Jwt,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub mode: AuthMode,
+    /// Lifetime of issued JWTs, in seconds.
+    #[serde(default = "default_token_ttl")]
+    pub token_ttl: u64,
+    #[serde(default)]
+    pub keys: Vec<KeyConfig>,
+}
+
+fn default_token_ttl() -> u64 {
+    15 * 60
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            mode: AuthMode::default(),
+            token_ttl: default_token_ttl(),
+            keys: vec![],
+        }
+    }
+}
+
 pub fn authorize_user(user: &User, credentials: &Credentials) -> Result<String, Error> {
     let db_hash = PasswordHash::new(&user.password)?;
     let argon = Argon2::default();
 
     argon.verify_password(credentials.password.as_bytes(), &db_hash)?;
 
-    Ok(rand::thread_rng()
+    Ok(random_token(128))
+}
+
+pub fn random_token(length: usize) -> String {
+    rand::thread_rng()
         .sample_iter(&Alphanumeric)
-        .take(128)
+        .take(length)
         .map(char::from)
-        .collect())
+        .collect()
 }
 
 pub fn hash_password(password: String) -> Result<String, Error> {
@@ -33,3 +76,46 @@ pub fn hash_password(password: String) -> Result<String, Error> {
     let password_hash = argon.hash_password(password.as_bytes(), &salt)?;
     Ok(password_hash.to_string())
 }
+
+fn unsubscribe_mac(secret: &str, subscription_id: i32) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("digest-unsubscribe:{}", subscription_id).as_bytes());
+    mac
+}
+
+/// The `X-Cr8s-Signature` of a webhook payload: a hex HMAC-SHA256 of
+/// `<timestamp>.<body>` under the webhook's secret, so a captured request
+/// cannot be replayed with another timestamp.
+pub fn sign_webhook_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    let signature = mac.finalize().into_bytes();
+    format!(
+        "sha256={}",
+        signature
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+/// A token that lets whoever holds it delete one digest subscription,
+/// without logging in: `<subscription id>.<HMAC-SHA256 signature>`.
+pub fn sign_unsubscribe_token(secret: &str, subscription_id: i32) -> String {
+    let signature = unsubscribe_mac(secret, subscription_id)
+        .finalize()
+        .into_bytes();
+    format!("{}.{}", subscription_id, URL_SAFE_NO_PAD.encode(signature))
+}
+
+pub fn verify_unsubscribe_token(secret: &str, token: &str) -> Option<i32> {
+    let (subscription_id, signature) = token.split_once('.')?;
+    let subscription_id = subscription_id.parse().ok()?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    unsubscribe_mac(secret, subscription_id)
+        .verify_slice(&signature)
+        .ok()?;
+    Some(subscription_id)
+}
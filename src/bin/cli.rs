@@ -1,8 +1,14 @@
-use clap::{Arg, Command};
+use std::path::PathBuf;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use clap::{Arg, ArgAction, Command};
 
 extern crate cr8s;
 
 fn main() {
+    // The workers log failed deliveries; `RUST_LOG` overrides the level.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     let matches = Command::new("Cr8s")
         .version("1.0")
         .propagate_version(true)
@@ -31,16 +37,180 @@ fn main() {
                             .required(true)
                             .value_parser(clap::value_parser!(i32)),
                     ),
+                )
+                .subcommand(
+                    Command::new("suspend")
+                        .about("Suspend a user and revoke their sessions")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .value_parser(clap::value_parser!(i32)),
+                        )
+                        .arg(Arg::new("reason").long("reason")),
+                )
+                .subcommand(
+                    Command::new("reinstate")
+                        .about("Reinstate a suspended user")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .value_parser(clap::value_parser!(i32)),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("scheduler")
+                .about("Recurring jobs from Scheduler.toml")
+                .arg_required_else_help(true)
+                .subcommand(Command::new("run").about("Run the scheduled jobs until stopped"))
+                .subcommand(Command::new("history").about("List the latest job runs")),
+        )
+        .subcommand(
+            Command::new("mail-queue")
+                .about("Outbound email queue")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("work")
+                        .about("Deliver queued emails")
+                        .arg(
+                            Arg::new("once")
+                                .long("once")
+                                .help("Stop once nothing is due")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("list").about("List queued emails").arg(
+                        Arg::new("status")
+                            .long("status")
+                            .value_parser(["pending", "sending", "sent", "dead"])
+                            .default_value("dead"),
+                    ),
+                )
+                .subcommand(
+                    Command::new("retry").about("Queue a dead email again").arg(
+                        Arg::new("id")
+                            .required(true)
+                            .value_parser(clap::value_parser!(i32)),
+                    ),
+                ),
+        )
+        .subcommand(
+            Command::new("webhooks")
+                .about("Outbound webhook deliveries")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("work")
+                        .about("Deliver queued webhook events")
+                        .arg(
+                            Arg::new("once")
+                                .long("once")
+                                .help("Stop once nothing is due")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export every crate or rustacean as CSV or NDJSON")
+                .arg(
+                    Arg::new("resource")
+                        .required(true)
+                        .value_parser(["crates", "rustaceans"]),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["csv", "ndjson"])
+                        .default_value("csv"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .help("Write to this file instead of stdout")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Create or update crates by code, or rustaceans by email, from a file")
+                .arg(
+                    Arg::new("resource")
+                        .required(true)
+                        .value_parser(["crates", "rustaceans"]),
+                )
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("Defaults to ndjson for .ndjson and .jsonl files, csv otherwise")
+                        .value_parser(["csv", "ndjson"]),
+                )
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .help("Show what would change without writing it")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("best_effort")
+                        .long("best-effort")
+                        .help("Write the valid lines even if others are invalid")
+                        .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
             Command::new("digest-send")
                 .about("Send an email with the newest crates")
-                .arg(Arg::new("to").required(true))
+                .arg(Arg::new("to").required_unless_present("all"))
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .help("Send to every subscriber whose digest is due")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["to", "hours_since", "since", "until"]),
+                )
+                .arg(
+                    Arg::new("locale")
+                        .long("locale")
+                        .help("Locale such as de or pt-BR; defaults to the recipient user's")
+                        .conflicts_with("all"),
+                )
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry-run")
+                        .help("Render the digest without sending it")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("all"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .help("Write the rendered HTML to this file instead of stdout")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .requires("dry_run"),
+                )
                 .arg(
                     Arg::new("hours_since")
-                        .required(true)
-                        .value_parser(clap::value_parser!(i32)),
+                        .help("Shorthand for --since <hours>h")
+                        .value_parser(clap::value_parser!(i64))
+                        .conflicts_with("since"),
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .help("Timestamp or duration like 7d; defaults to the end of the last digest")
+                        .value_parser(cr8s::commands::parse_point_in_time),
+                )
+                .arg(
+                    Arg::new("until")
+                        .long("until")
+                        .help("Timestamp or duration like 1d; defaults to now")
+                        .value_parser(cr8s::commands::parse_point_in_time),
                 ),
         )
         .get_matches();
@@ -66,15 +236,75 @@ fn main() {
             Some(("delete", sub_matches)) => {
                 cr8s::commands::delete_user(sub_matches.get_one::<i32>("id").unwrap().to_owned())
             }
+            Some(("suspend", sub_matches)) => cr8s::commands::suspend_user(
+                sub_matches.get_one::<i32>("id").unwrap().to_owned(),
+                sub_matches.get_one::<String>("reason").cloned(),
+            ),
+            Some(("reinstate", sub_matches)) => {
+                cr8s::commands::reinstate_user(sub_matches.get_one::<i32>("id").unwrap().to_owned())
+            }
             _ => {}
         },
-        Some(("digest-send", sub_matches)) => cr8s::commands::send_digest(
-            sub_matches.get_one::<String>("to").unwrap().to_owned(),
-            sub_matches
-                .get_one::<i32>("hours_since")
-                .unwrap()
-                .to_owned(),
+        Some(("scheduler", sub_matches)) => match sub_matches.subcommand() {
+            Some(("run", _)) => cr8s::commands::run_scheduler(),
+            Some(("history", _)) => cr8s::commands::list_job_runs(),
+            _ => {}
+        },
+        Some(("mail-queue", sub_matches)) => match sub_matches.subcommand() {
+            Some(("work", sub_matches)) => {
+                cr8s::commands::work_mail_queue(sub_matches.get_flag("once"))
+            }
+            Some(("list", sub_matches)) => cr8s::commands::list_outbound_emails(
+                sub_matches.get_one::<String>("status").unwrap().to_owned(),
+            ),
+            Some(("retry", sub_matches)) => cr8s::commands::retry_outbound_email(
+                sub_matches.get_one::<i32>("id").unwrap().to_owned(),
+            ),
+            _ => {}
+        },
+        Some(("webhooks", sub_matches)) => {
+            if let Some(("work", sub_matches)) = sub_matches.subcommand() {
+                cr8s::commands::work_webhooks(sub_matches.get_flag("once"))
+            }
+        }
+        Some(("export", sub_matches)) => cr8s::commands::export_catalog(
+            sub_matches.get_one::<String>("resource").unwrap().to_owned(),
+            sub_matches.get_one::<String>("format").unwrap().to_owned(),
+            sub_matches.get_one::<PathBuf>("output").cloned(),
+        ),
+        Some(("import", sub_matches)) => cr8s::commands::import_catalog(
+            sub_matches.get_one::<String>("resource").unwrap().to_owned(),
+            sub_matches.get_one::<PathBuf>("path").unwrap().to_owned(),
+            sub_matches.get_one::<String>("format").cloned(),
+            sub_matches.get_flag("dry_run"),
+            sub_matches.get_flag("best_effort"),
         ),
+        Some(("digest-send", sub_matches)) if sub_matches.get_flag("all") => {
+            cr8s::commands::send_all_digests()
+        }
+        Some(("digest-send", sub_matches)) => {
+            let to = sub_matches.get_one::<String>("to").unwrap().to_owned();
+            let since = sub_matches
+                .get_one::<NaiveDateTime>("since")
+                .cloned()
+                .or_else(|| {
+                    sub_matches
+                        .get_one::<i64>("hours_since")
+                        .map(|hours| Utc::now().naive_utc() - Duration::hours(*hours))
+                });
+            let until = sub_matches.get_one::<NaiveDateTime>("until").cloned();
+            let locale = sub_matches.get_one::<String>("locale").cloned();
+            match sub_matches.get_flag("dry_run") {
+                true => cr8s::commands::preview_digest(
+                    to,
+                    since,
+                    until,
+                    locale,
+                    sub_matches.get_one::<PathBuf>("output").cloned(),
+                ),
+                false => cr8s::commands::send_digest(to, since, until, locale),
+            }
+        }
         _ => {}
     }
 }
@@ -28,6 +28,7 @@ impl Fairing for CORS {
             "POST, PATCH, PUT, DELETE, HEAD, OPTIONS, GET",
         ));
         response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
+        response.set_header(Header::new("Access-Control-Expose-Headers", "ETag"));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
     }
 }
@@ -36,27 +37,37 @@ impl Fairing for CORS {
 #[rocket::main]
 async fn main() {
     let _ = rocket::build()
+        .mount(cr8s::rocket_routes::v1::PREFIX, cr8s::rocket_routes::v1::routes())
+        // Deprecated aliases of /api/v1, see `RootAliases`.
+        .mount("/", cr8s::rocket_routes::v1::routes())
+        // Unversioned, as these URLs end up in IdP settings, emails and feed
+        // readers, or describe every version.
         .mount(
             "/",
             routes![
                 cr8s::rocket_routes::options,
-                cr8s::rocket_routes::authorization::login,
-                cr8s::rocket_routes::authorization::me,
-                cr8s::rocket_routes::rustaceans::get_rustaceans,
-                cr8s::rocket_routes::rustaceans::view_rustacean,
-                cr8s::rocket_routes::rustaceans::create_rustacean,
-                cr8s::rocket_routes::rustaceans::update_rustacean,
-                cr8s::rocket_routes::rustaceans::delete_rustacean,
-                cr8s::rocket_routes::crates::get_crates,
-                cr8s::rocket_routes::crates::view_crate,
-                cr8s::rocket_routes::crates::create_crate,
-                cr8s::rocket_routes::crates::update_crate,
-                cr8s::rocket_routes::crates::delete_crate,
+                cr8s::rocket_routes::oidc::login,
+                cr8s::rocket_routes::oidc::callback,
+                cr8s::rocket_routes::openapi::spec,
+                cr8s::rocket_routes::openapi::docs,
+                cr8s::rocket_routes::feeds::crates_atom,
+                cr8s::rocket_routes::feeds::crates_rss,
+                cr8s::rocket_routes::feeds::rustacean_atom,
+                cr8s::rocket_routes::subscriptions::confirm_unsubscribe,
+                cr8s::rocket_routes::subscriptions::unsubscribe,
             ],
         )
+        .register("/", catchers![cr8s::rocket_routes::unprocessable])
         .attach(CORS)
+        .attach(cr8s::rocket_routes::admin::AuditLog)
+        .attach(cr8s::rocket_routes::v1::RootAliases::default())
         .attach(cr8s::rocket_routes::DbConn::fairing())
         .attach(cr8s::rocket_routes::CacheConn::init())
+        .attach(cr8s::rocket_routes::authorization::fairing())
+        .attach(cr8s::rocket_routes::oidc::fairing())
+        .attach(cr8s::rocket_routes::subscriptions::fairing())
+        .attach(cr8s::rocket_routes::feeds::fairing())
+        .attach(cr8s::rocket_routes::openapi::assets_fairing())
         .launch()
         .await;
 }
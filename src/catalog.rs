@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::str::FromStr;
+
+use diesel::{Connection, PgConnection};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::models;
+use crate::repositories::{CrateRepository, RustaceanRepository};
+use crate::rocket_routes::crates::UNKNOWN_RUSTACEAN;
+use crate::rocket_routes::{field_messages, v1};
+use crate::webhooks;
+
+/// Rows per query, and so per chunk of an export.
+pub const PAGE_SIZE: i64 = 500;
+
+pub type CatalogError = Box<dyn Error + Send + Sync>;
+
+/// An encoded page of an export and the id to continue after.
+pub type Page = Option<(Vec<u8>, i32)>;
+
+/// The formats crates and rustaceans are exported and imported in, with the
+/// fields of the v1 API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "csv" => Ok(Format::Csv),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(format!("Unknown format {}, expected csv or ndjson", format)),
+        }
+    }
+}
+
+/// `rows` as CSV, with a header line if `header`, or as NDJSON.
+pub fn encode<T: Serialize>(
+    format: Format,
+    rows: &[T],
+    header: bool,
+) -> Result<Vec<u8>, CatalogError> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(header)
+                .from_writer(vec![]);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.into_inner().map_err(|e| e.into_error().into())
+        }
+        Format::Ndjson => {
+            let mut bytes = vec![];
+            for row in rows {
+                serde_json::to_writer(&mut bytes, row)?;
+                bytes.push(b'\n');
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// A page of the crates after the id `after`, encoded, with the id to
+/// continue after, or `None` past the last crate. Starting from `0`, the
+/// first page carries the CSV header.
+pub fn export_crates(
+    c: &PgConnection,
+    format: Format,
+    after: i32,
+) -> Result<Page, CatalogError> {
+    let crates = CrateRepository::find_page(c, after, PAGE_SIZE)?;
+    let last = match crates.last() {
+        Some(a_crate) => a_crate.id,
+        None => return Ok(None),
+    };
+    let crates: Vec<v1::Crate> = crates.into_iter().map(v1::Crate::from).collect();
+    Ok(Some((encode(format, &crates, after == 0)?, last)))
+}
+
+/// Like `export_crates`, for rustaceans.
+pub fn export_rustaceans(
+    c: &PgConnection,
+    format: Format,
+    after: i32,
+) -> Result<Page, CatalogError> {
+    let rustaceans = RustaceanRepository::find_page(c, after, PAGE_SIZE)?;
+    let last = match rustaceans.last() {
+        Some(rustacean) => rustacean.id,
+        None => return Ok(None),
+    };
+    let rustaceans: Vec<v1::Rustacean> =
+        rustaceans.into_iter().map(v1::Rustacean::from).collect();
+    Ok(Some((encode(format, &rustaceans, after == 0)?, last)))
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Unchanged,
+    Error,
+}
+
+/// What importing a line did, or would do.
+#[derive(Serialize)]
+pub struct Line {
+    pub line: usize,
+    pub action: Action,
+    /// The row updated or left unchanged, or the one created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    /// `[old, new]` for each field an update changes.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub changes: BTreeMap<String, (Value, Value)>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub errors: BTreeMap<&'static str, Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    /// Whether the changes were written: not on a dry run, nor in
+    /// all-or-nothing mode once a line failed.
+    pub applied: bool,
+    pub lines: Vec<Line>,
+}
+
+impl Report {
+    pub fn has_errors(&self) -> bool {
+        self.lines.iter().any(|line| line.action == Action::Error)
+    }
+}
+
+/// The records of `input`, each with its line number. CSV counts the header
+/// as line 1, NDJSON skips blank lines.
+fn parse<T: DeserializeOwned>(format: Format, input: &str) -> Vec<(usize, Result<T, String>)> {
+    match format {
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(input.as_bytes());
+            let headers = match reader.headers() {
+                Ok(headers) => headers.clone(),
+                Err(e) => return vec![(1, Err(e.to_string()))],
+            };
+            reader
+                .records()
+                .enumerate()
+                .map(|(index, record)| match record {
+                    Ok(record) => (
+                        record.position().map_or(index + 2, |p| p.line() as usize),
+                        record
+                            .deserialize(Some(&headers))
+                            .map_err(|e| e.to_string()),
+                    ),
+                    Err(e) => (
+                        e.position().map_or(index + 2, |p| p.line() as usize),
+                        Err(e.to_string()),
+                    ),
+                })
+                .collect()
+        }
+        Format::Ndjson => input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| (index + 1, serde_json::from_str(line).map_err(|e| e.to_string())))
+            .collect(),
+    }
+}
+
+fn error(code: &'static str, message: String) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(message.into());
+    error
+}
+
+/// The import of each record, before anything is written.
+struct Plan<T> {
+    lines: Vec<Line>,
+    /// Index in `lines` and record of each row to create.
+    creates: Vec<(usize, T)>,
+    /// Index in `lines`, id and record of each row to update.
+    updates: Vec<(usize, i32, T)>,
+}
+
+impl<T: Serialize + Validate> Plan<T> {
+    /// Matches each valid record with the `existing` rows by its `key` field:
+    /// no row means a create, one a comparison of the record's fields with
+    /// the row's, and more than one an error. So does a key repeated within
+    /// the input. `check` adds the errors `validate` cannot find.
+    fn new(
+        records: Vec<(usize, Result<T, String>)>,
+        key_field: &'static str,
+        key: impl Fn(&T) -> &str,
+        existing: &HashMap<String, Vec<(i32, Value)>>,
+        check: impl Fn(&T, &mut ValidationErrors),
+    ) -> Self {
+        let mut plan = Plan {
+            lines: vec![],
+            creates: vec![],
+            updates: vec![],
+        };
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (line, record) in records {
+            let index = plan.lines.len();
+            let mut entry = Line {
+                line,
+                action: Action::Error,
+                id: None,
+                changes: BTreeMap::new(),
+                errors: BTreeMap::new(),
+            };
+            let record = match record {
+                Ok(record) => record,
+                Err(message) => {
+                    entry.errors.insert("line", vec![message]);
+                    plan.lines.push(entry);
+                    continue;
+                }
+            };
+            let mut errors = record.validate().err().unwrap_or_default();
+            check(&record, &mut errors);
+            let key = key(&record).to_owned();
+            if let Some(first) = seen.get(&key) {
+                errors.add(key_field, error("unique", format!("is also on line {}", first)));
+            } else {
+                seen.insert(key.to_owned(), line);
+            }
+            match existing.get(&key).map(Vec::as_slice) {
+                Some([_, _, ..]) => errors.add(
+                    key_field,
+                    error("ambiguous", "matches more than one row".to_owned()),
+                ),
+                Some([(id, current)]) => {
+                    entry.id = Some(*id);
+                    entry.changes = changes(current, &json!(record));
+                }
+                _ => {}
+            }
+            if !errors.is_empty() {
+                entry.errors = field_messages(&errors);
+                entry.changes.clear();
+            } else if entry.id.is_none() {
+                entry.action = Action::Create;
+                plan.creates.push((index, record));
+            } else if entry.changes.is_empty() {
+                entry.action = Action::Unchanged;
+            } else {
+                entry.action = Action::Update;
+                plan.updates.push((index, entry.id.unwrap(), record));
+            }
+            plan.lines.push(entry);
+        }
+        plan
+    }
+
+    fn applies(&self, dry_run: bool, all_or_nothing: bool) -> bool {
+        let failed = self.lines.iter().any(|line| line.action == Action::Error);
+        !dry_run && (!all_or_nothing || !failed)
+    }
+
+    fn report(self, applied: bool) -> Report {
+        Report {
+            applied,
+            lines: self.lines,
+        }
+    }
+}
+
+/// The fields of `new` that differ from `current`.
+fn changes(current: &Value, new: &Value) -> BTreeMap<String, (Value, Value)> {
+    let new = match new.as_object() {
+        Some(new) => new,
+        None => return BTreeMap::new(),
+    };
+    new.iter()
+        .filter(|(field, value)| current.get(field.as_str()) != Some(value))
+        .map(|(field, value)| {
+            let old = current.get(field.as_str()).cloned().unwrap_or(Value::Null);
+            (field.to_owned(), (old, value.clone()))
+        })
+        .collect()
+}
+
+fn by_key(rows: impl Iterator<Item = (String, i32, Value)>) -> HashMap<String, Vec<(i32, Value)>> {
+    let mut by_key: HashMap<String, Vec<(i32, Value)>> = HashMap::new();
+    for (key, id, row) in rows {
+        by_key.entry(key).or_default().push((id, row));
+    }
+    by_key
+}
+
+/// Creates or updates a crate per record of `input`, matched by `code`, in
+/// one transaction. Lines that fail are reported; in all-or-nothing mode
+/// they keep the other lines from being written, and a dry run writes
+/// nothing in any case.
+pub fn import_crates(
+    c: &PgConnection,
+    format: Format,
+    input: &str,
+    dry_run: bool,
+    all_or_nothing: bool,
+) -> Result<Report, CatalogError> {
+    let records = parse::<v1::NewCrate>(format, input);
+    let parsed: Vec<&v1::NewCrate> = records.iter().filter_map(|(_, r)| r.as_ref().ok()).collect();
+    let codes = parsed.iter().map(|a_crate| a_crate.code.to_owned()).collect();
+    let rustacean_ids = parsed.iter().map(|a_crate| a_crate.rustacean_id).collect();
+    c.transaction(|| {
+        let known: HashSet<i32> = RustaceanRepository::find_by_ids(c, rustacean_ids)?
+            .into_iter()
+            .map(|rustacean| rustacean.id)
+            .collect();
+        let existing = by_key(
+            CrateRepository::find_by_codes(c, codes)?
+                .into_iter()
+                .map(|a_crate| {
+                    let code = a_crate.code.to_owned();
+                    (code, a_crate.id, json!(v1::Crate::from(a_crate)))
+                }),
+        );
+        let mut plan = Plan::new(
+            records,
+            "code",
+            |a_crate: &v1::NewCrate| a_crate.code.as_str(),
+            &existing,
+            |a_crate, errors| {
+                if !known.contains(&a_crate.rustacean_id) {
+                    errors.add("rustacean_id", error("exists", UNKNOWN_RUSTACEAN.to_owned()));
+                }
+            },
+        );
+        if !plan.applies(dry_run, all_or_nothing) {
+            return Ok(plan.report(false));
+        }
+
+        let (indexes, new_crates): (Vec<usize>, Vec<models::NewCrate>) = plan
+            .creates
+            .drain(..)
+            .map(|(index, a_crate)| (index, a_crate.into()))
+            .unzip();
+        let created = CrateRepository::create_multiple(c, new_crates)?;
+        for (index, a_crate) in indexes.into_iter().zip(created) {
+            plan.lines[index].id = Some(a_crate.id);
+            webhooks::enqueue(c, webhooks::CRATE_CREATED, json!(v1::Crate::from(a_crate)))?;
+        }
+        for (_, id, a_crate) in plan.updates.drain(..) {
+            let a_crate = CrateRepository::save(c, id, a_crate.into())?;
+            webhooks::enqueue(c, webhooks::CRATE_UPDATED, json!(v1::Crate::from(a_crate)))?;
+        }
+        Ok(plan.report(true))
+    })
+}
+
+/// Like `import_crates`, for rustaceans matched by `email`.
+pub fn import_rustaceans(
+    c: &PgConnection,
+    format: Format,
+    input: &str,
+    dry_run: bool,
+    all_or_nothing: bool,
+) -> Result<Report, CatalogError> {
+    let records = parse::<v1::NewRustacean>(format, input);
+    let emails = records
+        .iter()
+        .filter_map(|(_, r)| r.as_ref().ok())
+        .map(|rustacean| rustacean.email.to_owned())
+        .collect();
+    c.transaction(|| {
+        let existing = by_key(
+            RustaceanRepository::find_by_emails(c, emails)?
+                .into_iter()
+                .map(|rustacean| {
+                    let email = rustacean.email.to_owned();
+                    (email, rustacean.id, json!(v1::Rustacean::from(rustacean)))
+                }),
+        );
+        let mut plan = Plan::new(
+            records,
+            "email",
+            |rustacean: &v1::NewRustacean| rustacean.email.as_str(),
+            &existing,
+            |_, _| {},
+        );
+        if !plan.applies(dry_run, all_or_nothing) {
+            return Ok(plan.report(false));
+        }
+
+        let (indexes, new_rustaceans): (Vec<usize>, Vec<models::NewRustacean>) = plan
+            .creates
+            .drain(..)
+            .map(|(index, rustacean)| (index, rustacean.into()))
+            .unzip();
+        let created = RustaceanRepository::create_multiple(c, new_rustaceans)?;
+        for (index, rustacean) in indexes.into_iter().zip(created) {
+            plan.lines[index].id = Some(rustacean.id);
+            let rustacean = json!(v1::Rustacean::from(rustacean));
+            webhooks::enqueue(c, webhooks::RUSTACEAN_CREATED, rustacean)?;
+        }
+        for (_, id, rustacean) in plan.updates.drain(..) {
+            let rustacean = RustaceanRepository::save(c, id, rustacean.into())?;
+            let rustacean = json!(v1::Rustacean::from(rustacean));
+            webhooks::enqueue(c, webhooks::RUSTACEAN_UPDATED, rustacean)?;
+        }
+        Ok(plan.report(true))
+    })
+}
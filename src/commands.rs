@@ -1,24 +1,115 @@
-use chrono::{Datelike, Utc};
-use diesel::{Connection, PgConnection};
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use diesel::{Connection, OptionalExtension, PgConnection, QueryResult};
 use lettre::transport::smtp::authentication::Credentials;
+use lettre::SmtpTransport;
+use rocket_db_pools::deadpool_redis::redis::{self, Commands};
 use tera::{Context, Tera};
 
 use crate::auth;
-use crate::mail::HtmlMailer;
-use crate::models::{NewUser, RoleCode};
-use crate::repositories::{CrateRepository, RoleRepository, UserRepository};
+use crate::catalog::{self, Action, Format};
+use crate::i18n::{self, Localizer};
+use crate::mail::{
+    Email, FileMailTransport, HtmlMailer, MailTransport, MemoryMailTransport, SmtpMailTransport,
+    StdoutMailTransport,
+};
+use crate::mail_queue::{self, QueueMailTransport};
+use crate::models::{Crate, DigestSubscription, EmailStatus, NewDigestRun, NewUser, RoleCode};
+use crate::repositories::{
+    AuditRepository, CrateRepository, DigestRunRepository, DigestSubscriptionRepository,
+    JobRunRepository, OutboundEmailRepository, RoleRepository, UserRepository,
+    WebhookDeliveryRepository,
+};
+use crate::{scheduler, webhooks};
 
-fn load_db_connection() -> PgConnection {
+pub(crate) fn load_db_connection() -> PgConnection {
     let database_url = std::env::var("DATABASE_URL").expect("Cannot load DB url from env");
     PgConnection::establish(&database_url).expect("Cannot connect to postgres")
 }
 
-fn load_template_engine() -> Tera {
-    Tera::new("templates/**/*.html").unwrap_or_else(|e| {
+fn load_cache_connection() -> redis::Connection {
+    let redis_url = std::env::var("REDIS_URL").expect("Cannot load Redis url from env");
+    redis::Client::open(redis_url)
+        .and_then(|client| client.get_connection())
+        .expect("Cannot connect to redis")
+}
+
+/// Picks the backend from `MAIL_TRANSPORT`: `smtp` (the default), `file`
+/// (a maildir under `MAIL_DIR`), `stdout` or `memory`.
+fn load_mail_transport() -> Box<dyn MailTransport> {
+    match std::env::var("MAIL_TRANSPORT").as_deref() {
+        Ok("file") => Box::new(FileMailTransport {
+            dir: std::env::var("MAIL_DIR")
+                .expect("Cannot load mail directory from env")
+                .into(),
+        }),
+        Ok("stdout") => Box::new(StdoutMailTransport),
+        Ok("memory") => Box::new(MemoryMailTransport::default()),
+        Ok("smtp") | Err(_) => {
+            let smtp_host = std::env::var("SMTP_HOST").expect("Cannot load SMTP host from env");
+            let smtp_username =
+                std::env::var("SMTP_USERNAME").expect("Cannot load SMTP username from env");
+            let smtp_password =
+                std::env::var("SMTP_PASSWORD").expect("Cannot load SMTP password from env");
+
+            let credentials = Credentials::new(smtp_username, smtp_password);
+            let transport = SmtpTransport::relay(&smtp_host)
+                .expect("Cannot create SMTP transport")
+                .credentials(credentials)
+                .build();
+            Box::new(SmtpMailTransport { transport })
+        }
+        Ok(other) => panic!("Unknown mail transport {}", other),
+    }
+}
+
+/// Mail goes through the `outbound_emails` queue, `mail-queue work` hands
+/// it to the transport picked by `MAIL_TRANSPORT`.
+fn load_mail_queue() -> Box<dyn MailTransport> {
+    Box::new(QueueMailTransport {
+        connection: load_db_connection(),
+    })
+}
+
+pub(crate) fn load_template_engine() -> Tera {
+    Tera::new("templates/**/*.{html,txt,xml}").unwrap_or_else(|e| {
         panic!("Parsing error(s): {}", e);
     })
 }
 
+/// `MAIL_FROM`, `MAIL_DIGEST_SUBJECT` and `MAIL_LIST_UNSUBSCRIBE` are Tera
+/// templates, see `HtmlMailer`.
+fn load_mailer(transport: Box<dyn MailTransport>) -> HtmlMailer {
+    HtmlMailer {
+        template_engine: load_template_engine(),
+        transport,
+        from: std::env::var("MAIL_FROM").unwrap_or("Cr8s <info@cr8s.com>".to_owned()),
+        subject: std::env::var("MAIL_DIGEST_SUBJECT")
+            .unwrap_or("{{ t(key=\"digest-subject\") }}".to_owned()),
+        list_unsubscribe: Some(std::env::var("MAIL_LIST_UNSUBSCRIBE").unwrap_or(
+            "{% if unsubscribe_url %}<{{ unsubscribe_url }}>, {% endif %}\
+             <mailto:info@cr8s.com?subject=Unsubscribe%20{{ to | urlencode }}>"
+                .to_owned(),
+        )),
+        localizer: Some(load_localizer()),
+    }
+}
+
+/// Catalogs come from `locales/`, recipients without a locale or whose
+/// locale has no catalog get `MAIL_DEFAULT_LOCALE` (`en` by default).
+fn load_localizer() -> Arc<Localizer> {
+    let default_locale =
+        std::env::var("MAIL_DEFAULT_LOCALE").unwrap_or(i18n::DEFAULT_LOCALE.to_owned());
+    let localizer = Localizer::load(Path::new("locales"), &default_locale)
+        .unwrap_or_else(|e| panic!("Cannot load locales: {}", e));
+    Arc::new(localizer)
+}
+
 pub fn create_user(username: String, password: String, role_codes: Vec<String>) {
     let mut c = load_db_connection();
 
@@ -26,6 +117,9 @@ pub fn create_user(username: String, password: String, role_codes: Vec<String>)
     let new_user = NewUser {
         username,
         password: password_hash,
+        email: None,
+        oidc_subject: None,
+        locale: None,
     };
     let role_codes = role_codes
         .iter()
@@ -52,30 +146,496 @@ pub fn delete_user(id: i32) {
     UserRepository::delete(&mut c, id).unwrap();
 }
 
-pub fn send_digest(to: String, hours_since: i32) {
-    let mut c = load_db_connection();
-    let tera = load_template_engine();
+/// Mirrors `revoke_user_sessions` in the server. The CLI doesn't know the
+/// configured JWT lifetime, so the denylist entry is kept for a day.
+fn revoke_user_sessions(cache: &mut redis::Connection, user_id: i32) {
+    let user_sessions = format!("user_sessions/{}", user_id);
+    let session_ids: Vec<String> = cache.smembers(&user_sessions).unwrap();
+    let mut keys: Vec<String> = session_ids
+        .iter()
+        .map(|id| format!("sessions/{}", id))
+        .collect();
+    keys.push(user_sessions);
+    cache.del::<_, ()>(keys).unwrap();
+    cache
+        .set_ex::<_, _, ()>(
+            format!("revoked_users/{}", user_id),
+            Utc::now().timestamp() as u64,
+            24 * 60 * 60,
+        )
+        .unwrap();
+}
+
+pub fn suspend_user(id: i32, reason: Option<String>) {
+    let c = load_db_connection();
+    let mut cache = load_cache_connection();
+
+    let user = UserRepository::suspend(&c, id, reason).unwrap();
+    revoke_user_sessions(&mut cache, user.id);
+    println!("User suspended {:?}", user);
+}
+
+pub fn reinstate_user(id: i32) {
+    let c = load_db_connection();
+
+    let user = UserRepository::reinstate(&c, id).unwrap();
+    println!("User reinstated {:?}", user);
+}
+
+/// Parses `--since`/`--until` values: an RFC 3339 timestamp, a `YYYY-MM-DD`
+/// date, or a duration back from now such as `30m`, `24h`, `7d` or `2w`.
+pub fn parse_point_in_time(value: &str) -> Result<NaiveDateTime, String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime.naive_utc());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap());
+    }
 
-    let crates = CrateRepository::find_since(&mut c, hours_since).unwrap();
+    let invalid = || format!("'{}' is neither a timestamp nor a duration like 7d", value);
+    if value.len() < 2 || !value.is_char_boundary(value.len() - 1) {
+        return Err(invalid());
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => return Err(invalid()),
+    };
+    Ok(Utc::now().naive_utc() - duration)
+}
+
+/// Sends the crates created in `[since, until)`, see `digest_window` for the
+/// defaults. Without `locale`, the digest is in the locale of the user with
+/// that email address, if any.
+pub fn send_digest(
+    to: String,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    locale: Option<String>,
+) {
+    send_digest_with(load_mail_queue(), to, since, until, locale)
+}
+
+pub fn send_digest_with(
+    transport: Box<dyn MailTransport>,
+    to: String,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    locale: Option<String>,
+) {
+    let c = load_db_connection();
+
+    let (since, until) = digest_window(&c, &to, since, until).unwrap();
+    if since >= until {
+        println!("Nothing to send, the digest is up to date until {}", since);
+        return;
+    }
+
+    let crates = CrateRepository::find_between(&c, since, until).unwrap();
     if crates.len() > 0 {
-        println!("Sending the digest for {} crates", crates.len());
-        let year = Utc::now().year();
-        let mut context = Context::new();
-        context.insert("crates", &crates);
-        context.insert("year", &year);
-
-        let smtp_host = std::env::var("SMTP_HOST").expect("Cannot load SMTP host from env");
-        let smtp_username =
-            std::env::var("SMTP_USERNAME").expect("Cannot load SMTP username from env");
-        let smtp_password =
-            std::env::var("SMTP_PASSWORD").expect("Cannot load SMTP password from env");
-
-        let credentials = Credentials::new(smtp_username, smtp_password);
-        let mailer = HtmlMailer {
-            smtp_host,
-            credentials,
-            template_engine: tera,
+        println!(
+            "Sending the digest for {} crates created between {} and {}",
+            crates.len(),
+            since,
+            until
+        );
+        let locale = recipient_locale(&c, &to, locale).unwrap();
+        let context = digest_context(&crates, since, until);
+        let mailer = load_mailer(transport);
+        mailer
+            .send(&to, locale.as_deref(), "email/digest", &context)
+            .unwrap();
+    }
+
+    let new_run = NewDigestRun {
+        recipient: to,
+        since,
+        until,
+    };
+    DigestRunRepository::create(&c, new_run).unwrap();
+}
+
+/// Renders the digest `send_digest` would send, without sending it or
+/// recording a run, into `output` or to stdout.
+pub fn preview_digest(
+    to: String,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    locale: Option<String>,
+    output: Option<PathBuf>,
+) {
+    let c = load_db_connection();
+
+    let (since, until) = digest_window(&c, &to, since, until).unwrap();
+    let locale = recipient_locale(&c, &to, locale).unwrap();
+    let crates = CrateRepository::find_between(&c, since, until).unwrap();
+    let email = render_digest(&to, locale.as_deref(), &crates, since, until).unwrap();
+    match output {
+        Some(output) => {
+            std::fs::write(&output, email.html_body).unwrap();
+            println!(
+                "Digest for {} with {} crates created between {} and {} written to {}",
+                to,
+                crates.len(),
+                since,
+                until,
+                output.display()
+            );
+        }
+        None => println!("{}", email.html_body),
+    }
+}
+
+/// Without `since` the window starts where the last digest to `to` ended, or
+/// 24 hours back for a new recipient; `until` defaults to now.
+pub(crate) fn digest_window(
+    c: &PgConnection,
+    to: &String,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+) -> QueryResult<(NaiveDateTime, NaiveDateTime)> {
+    let until = until.unwrap_or_else(|| Utc::now().naive_utc());
+    let since = match since {
+        Some(since) => since,
+        None => DigestRunRepository::find_last_until(c, to)?
+            .unwrap_or(until - Duration::hours(24)),
+    };
+    Ok((since, until))
+}
+
+pub(crate) fn recipient_locale(
+    c: &PgConnection,
+    to: &String,
+    locale: Option<String>,
+) -> QueryResult<Option<String>> {
+    match locale {
+        Some(locale) => Ok(Some(locale)),
+        None => Ok(UserRepository::find_by_email(c, to)
+            .optional()?
+            .and_then(|user| user.locale)),
+    }
+}
+
+/// The digest email exactly as it would be queued, for previews.
+pub(crate) fn render_digest(
+    to: &String,
+    locale: Option<&str>,
+    crates: &Vec<Crate>,
+    since: NaiveDateTime,
+    until: NaiveDateTime,
+) -> Result<Email, Box<dyn Error>> {
+    let mailer = load_mailer(Box::new(MemoryMailTransport::default()));
+    let context = digest_context(crates, since, until);
+    mailer.render(to, locale, "email/digest", &context)
+}
+
+fn digest_context(crates: &Vec<Crate>, since: NaiveDateTime, until: NaiveDateTime) -> Context {
+    let year = Utc::now().year();
+    let mut context = Context::new();
+    context.insert("crates", crates);
+    context.insert("year", &year);
+    context.insert("since", &since);
+    context.insert("until", &until);
+    context
+}
+
+fn subscription_matches(subscription: &DigestSubscription, a_crate: &Crate) -> bool {
+    if subscription.owner_id.is_some_and(|id| id != a_crate.rustacean_id) {
+        return false;
+    }
+    match &subscription.keyword {
+        Some(keyword) => {
+            let keyword = keyword.to_lowercase();
+            [Some(&a_crate.code), Some(&a_crate.name), a_crate.description.as_ref()]
+                .iter()
+                .flatten()
+                .any(|field| field.to_lowercase().contains(&keyword))
+        }
+        None => true,
+    }
+}
+
+/// Sends every due subscription the crates created since its last digest,
+/// narrowed down by its filters. Unsubscribe links are signed with
+/// `DIGEST_SECRET` and point at `APP_URL`.
+pub fn send_all_digests() {
+    send_all_digests_with(load_mail_queue())
+}
+
+pub fn send_all_digests_with(transport: Box<dyn MailTransport>) {
+    let c = load_db_connection();
+    let secret = std::env::var("DIGEST_SECRET").expect("Cannot load digest secret from env");
+    let app_url = std::env::var("APP_URL").unwrap_or("http://127.0.0.1:8000".to_owned());
+    let mailer = load_mailer(transport);
+
+    let until = Utc::now().naive_utc();
+    let subscriptions = DigestSubscriptionRepository::find_with_subscribers(&c).unwrap();
+    for (subscription, user, rustacean) in subscriptions {
+        let period = subscription.frequency.period();
+        // Scheduled runs drift by a few seconds, don't skip a day over it.
+        let due = subscription
+            .last_sent_at
+            .is_none_or(|last| last + period - Duration::hours(1) <= until);
+        if !due {
+            continue;
+        }
+        let locale = subscription
+            .locale
+            .clone()
+            .or_else(|| user.as_ref().and_then(|u| u.locale.clone()));
+        let to = match user.and_then(|u| u.email).or(rustacean.map(|r| r.email)) {
+            Some(to) => to,
+            None => {
+                println!("Subscription {} has no email address, skipping", subscription.id);
+                continue;
+            }
+        };
+
+        let since = subscription.last_sent_at.unwrap_or(until - period);
+        let crates: Vec<Crate> = CrateRepository::find_between(&c, since, until)
+            .unwrap()
+            .into_iter()
+            .filter(|a_crate| subscription_matches(&subscription, a_crate))
+            .collect();
+
+        if !crates.is_empty() {
+            println!(
+                "Sending subscription {} to {} with {} crates",
+                subscription.id,
+                to,
+                crates.len()
+            );
+            let mut context = digest_context(&crates, since, until);
+            context.insert(
+                "unsubscribe_url",
+                &format!(
+                    "{}/digest/unsubscribe?token={}",
+                    app_url,
+                    auth::sign_unsubscribe_token(&secret, subscription.id)
+                ),
+            );
+            if let Err(e) = mailer.send(&to, locale.as_deref(), "email/digest", &context) {
+                println!("Cannot send subscription {}: {}", subscription.id, e);
+                continue;
+            }
+        }
+        DigestSubscriptionRepository::mark_sent(&c, subscription.id, until).unwrap();
+    }
+}
+
+/// Sessions expire from Redis on their own, but their ids linger in the
+/// `user_sessions/<id>` sets used to log a user out everywhere.
+pub fn cleanup_sessions() {
+    let mut cache = load_cache_connection();
+
+    let keys: Vec<String> = cache
+        .scan_match::<_, String>("user_sessions/*")
+        .unwrap()
+        .collect();
+    let mut removed = 0;
+    for key in keys {
+        let session_ids: Vec<String> = cache.smembers(&key).unwrap();
+        for session_id in session_ids {
+            let exists: bool = cache.exists(format!("sessions/{}", session_id)).unwrap();
+            if !exists {
+                cache.srem::<_, _, ()>(&key, &session_id).unwrap();
+                removed += 1;
+            }
+        }
+    }
+    println!("Removed {} expired sessions", removed);
+}
+
+/// Deletes audit entries, digest runs and job runs older than `days`.
+pub fn apply_retention(days: i64) {
+    let c = load_db_connection();
+
+    let cutoff = Utc::now().naive_utc() - Duration::days(days);
+    let audit_entries = AuditRepository::delete_before(&c, cutoff).unwrap();
+    let digest_runs = DigestRunRepository::delete_before(&c, cutoff).unwrap();
+    let job_runs = JobRunRepository::delete_before(&c, cutoff).unwrap();
+    let deliveries = WebhookDeliveryRepository::delete_before(&c, cutoff).unwrap();
+    println!(
+        "Deleted {} audit entries, {} digest runs, {} job runs and {} webhook deliveries \
+         from before {}",
+        audit_entries, digest_runs, job_runs, deliveries, cutoff
+    );
+}
+
+pub fn run_scheduler() {
+    scheduler::run(scheduler::load_config());
+}
+
+pub fn list_job_runs() {
+    let c = load_db_connection();
+
+    let job_runs = JobRunRepository::find_multiple(&c, 50).unwrap();
+    for job_run in job_runs {
+        let outcome = match job_run.succeeded {
+            Some(true) => "succeeded".to_owned(),
+            Some(false) => format!("failed: {}", job_run.error.unwrap_or_default()),
+            None => "running".to_owned(),
         };
-        mailer.send(&to, "email/digest.html", &context).unwrap();
+        println!(
+            "#{} {} started: {} finished: {} {}",
+            job_run.id,
+            job_run.job,
+            job_run.started_at,
+            job_run.finished_at.map_or("-".to_owned(), |t| t.to_string()),
+            outcome
+        );
+    }
+}
+
+/// Delivers queued mail, polling for more every few seconds. With `once` it
+/// stops as soon as nothing is due.
+pub fn work_mail_queue(once: bool) {
+    let c = load_db_connection();
+    let transport = load_mail_transport();
+
+    loop {
+        let delivery = mail_queue::deliver_due(&c, transport.as_ref(), 50).unwrap();
+        if delivery.sent + delivery.retried + delivery.dead > 0 {
+            println!("Mail queue: {:?}", delivery);
+            continue;
+        }
+        if once {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_secs(5));
+    }
+}
+
+pub fn work_webhooks(once: bool) {
+    let c = load_db_connection();
+    let client = webhooks::client();
+
+    loop {
+        let delivery = webhooks::deliver_due(&c, &client, 50).unwrap();
+        if delivery.sent + delivery.retried + delivery.dead > 0 {
+            println!("Webhooks: {:?}", delivery);
+            continue;
+        }
+        if once {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_secs(5));
+    }
+}
+
+pub fn list_outbound_emails(status: String) {
+    let c = load_db_connection();
+
+    let status = EmailStatus::from_string(status).unwrap();
+    let emails = OutboundEmailRepository::find_by_status(&c, status, 100).unwrap();
+    for email in emails {
+        println!(
+            "#{} {} to {} \"{}\" attempts: {} next attempt: {} last error: {}",
+            email.id,
+            email.status.as_str(),
+            email.to_address,
+            email.subject,
+            email.attempts,
+            email.next_attempt_at,
+            email.last_error.unwrap_or_default()
+        );
+    }
+}
+
+pub fn retry_outbound_email(id: i32) {
+    let c = load_db_connection();
+
+    let email = OutboundEmailRepository::retry(&c, id).unwrap();
+    println!("Email #{} to {} queued again", email.id, email.to_address);
+}
+
+/// Writes every crate or rustacean to `output`, or stdout, a page at a time.
+pub fn export_catalog(resource: String, format: String, output: Option<PathBuf>) {
+    let c = load_db_connection();
+
+    let format: Format = format.parse().unwrap();
+    let page = match resource.as_str() {
+        "crates" => catalog::export_crates,
+        _ => catalog::export_rustaceans,
+    };
+    let mut out: Box<dyn Write> = match &output {
+        Some(output) => Box::new(File::create(output).unwrap()),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut after = 0;
+    while let Some((chunk, last)) = page(&c, format, after).unwrap() {
+        out.write_all(&chunk).unwrap();
+        after = last;
+    }
+    out.flush().unwrap();
+}
+
+/// Imports the crates or rustaceans in `path`, CSV unless the extension is
+/// `.ndjson` or `.jsonl`, printing a diff line per record: `+` created, `~`
+/// updated with the changed fields, `=` unchanged and `!` invalid. A dry run
+/// writes nothing, and neither does an invalid line unless `best_effort`.
+pub fn import_catalog(
+    resource: String,
+    path: PathBuf,
+    format: Option<String>,
+    dry_run: bool,
+    best_effort: bool,
+) {
+    let c = load_db_connection();
+
+    let format = match format {
+        Some(format) => format.parse().unwrap(),
+        None => match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ndjson") | Some("jsonl") => Format::Ndjson,
+            _ => Format::Csv,
+        },
+    };
+    let input = std::fs::read_to_string(&path).unwrap();
+    let import = match resource.as_str() {
+        "crates" => catalog::import_crates,
+        _ => catalog::import_rustaceans,
+    };
+    let report = import(&c, format, &input, dry_run, !best_effort).unwrap();
+
+    let mut counts = [0; 4];
+    for line in &report.lines {
+        let id = line.id.map(|id| format!(" #{}", id)).unwrap_or_default();
+        match line.action {
+            Action::Create => println!("+ line {}: create{}", line.line, id),
+            Action::Update => {
+                let changes: Vec<String> = line
+                    .changes
+                    .iter()
+                    .map(|(field, (old, new))| format!("{} {} -> {}", field, old, new))
+                    .collect();
+                println!("~ line {}: update{}: {}", line.line, id, changes.join(", "));
+            }
+            Action::Unchanged => println!("= line {}: unchanged{}", line.line, id),
+            Action::Error => {
+                let errors: Vec<String> = line
+                    .errors
+                    .iter()
+                    .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")))
+                    .collect();
+                println!("! line {}: {}", line.line, errors.join("; "));
+            }
+        }
+        counts[line.action as usize] += 1;
+    }
+    println!(
+        "{} to create, {} to update, {} unchanged, {} invalid",
+        counts[0], counts[1], counts[2], counts[3]
+    );
+    match (report.applied, dry_run) {
+        (true, _) => println!("Imported into {}", resource),
+        (false, true) => println!("Dry run, nothing was written"),
+        (false, false) => println!("Nothing was written; --best-effort skips the invalid lines"),
+    }
+    if report.has_errors() {
+        std::process::exit(1);
     }
 }
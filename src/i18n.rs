@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{Locale, NaiveDateTime, TimeZone, Utc};
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use unic_langid::LanguageIdentifier;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Fluent message catalogs, one per locale, read from `locales/<locale>/*.ftl`.
+pub struct Localizer {
+    bundles: HashMap<String, FluentBundle<FluentResource>>,
+    default_locale: String,
+}
+
+impl Localizer {
+    pub fn load(dir: &Path, default_locale: &str) -> Result<Self, Box<dyn Error>> {
+        let mut bundles = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let locale = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or("Invalid locale directory name")?
+                .to_owned();
+            let langid: LanguageIdentifier = locale.parse()?;
+
+            let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+            // Unicode isolation marks around placeables end up verbatim in
+            // plain text emails.
+            bundle.set_use_isolating(false);
+            for file in fs::read_dir(&path)? {
+                let file = file?.path();
+                if file.extension().is_none_or(|ext| ext != "ftl") {
+                    continue;
+                }
+                let source = fs::read_to_string(&file)?;
+                let resource = FluentResource::try_new(source)
+                    .map_err(|(_, e)| format!("Cannot parse {}: {:?}", file.display(), e))?;
+                bundle
+                    .add_resource(resource)
+                    .map_err(|e| format!("Cannot load {}: {:?}", file.display(), e))?;
+            }
+            bundles.insert(locale, bundle);
+        }
+
+        if !bundles.contains_key(default_locale) {
+            return Err(format!("No catalog for the default locale {}", default_locale).into());
+        }
+        Ok(Localizer {
+            bundles,
+            default_locale: default_locale.to_owned(),
+        })
+    }
+
+    /// The loaded locales to try for `requested`, most specific first:
+    /// `pt-BR` falls back to `pt`, then to the default locale.
+    pub fn fallback_chain(&self, requested: Option<&str>) -> Vec<&str> {
+        let mut candidates = vec![];
+        if let Some(langid) = requested.and_then(|r| r.parse::<LanguageIdentifier>().ok()) {
+            candidates.push(langid.to_string());
+            candidates.push(langid.language.to_string());
+        }
+        candidates.push(self.default_locale.to_owned());
+
+        let mut chain: Vec<&str> = vec![];
+        for candidate in candidates {
+            if let Some((locale, _)) = self.bundles.get_key_value(&candidate) {
+                if !chain.contains(&locale.as_str()) {
+                    chain.push(locale.as_str());
+                }
+            }
+        }
+        chain
+    }
+
+    /// The message `key` from the first locale in the chain that has it, or
+    /// the key itself so a missing translation is visible but not fatal.
+    pub fn translate(&self, chain: &[&str], key: &str, args: Option<&FluentArgs>) -> String {
+        for locale in chain {
+            let bundle = &self.bundles[*locale];
+            let pattern = match bundle.get_message(key).and_then(|m| m.value()) {
+                Some(pattern) => pattern,
+                None => continue,
+            };
+            let mut errors = vec![];
+            let value = bundle.format_pattern(pattern, args, &mut errors);
+            if !errors.is_empty() {
+                log::warn!("Cannot format {} in {}: {:?}", key, locale, errors);
+            }
+            return value.into_owned();
+        }
+        log::warn!("No translation for {} in {:?}", key, chain);
+        key.to_owned()
+    }
+
+    /// Formats with the chain's `datetime-format` message, a strftime pattern,
+    /// and the locale's month and day names.
+    pub fn format_datetime(&self, chain: &[&str], datetime: &NaiveDateTime) -> String {
+        let format = self.translate(chain, "datetime-format", None);
+        Utc.from_utc_datetime(datetime)
+            .format_localized(&format, chrono_locale(chain[0]))
+            .to_string()
+    }
+}
+
+/// Whether `locale` is a language tag such as `de` or `pt-BR` that fits the
+/// `varchar(16)` locale columns.
+pub fn is_valid_locale(locale: &str) -> bool {
+    locale.len() <= 16 && locale.parse::<LanguageIdentifier>().is_ok()
+}
+
+/// `de` has no region, so try `de_DE` before giving up on localised names.
+fn chrono_locale(locale: &str) -> Locale {
+    let posix = locale.replace('-', "_");
+    let with_region = format!("{}_{}", posix, posix.to_uppercase());
+    Locale::try_from(posix.as_str())
+        .or_else(|_| Locale::try_from(with_region.as_str()))
+        .unwrap_or(Locale::POSIX)
+}
+
+/// Exposes the catalogs to Tera for one recipient: `t(key="...", ...)`
+/// translates with the remaining arguments as Fluent variables, and the
+/// `localdate` filter formats a date.
+pub fn register(tera: &mut tera::Tera, localizer: Arc<Localizer>, locale: Option<&str>) {
+    let chain: Vec<String> = localizer
+        .fallback_chain(locale)
+        .into_iter()
+        .map(|l| l.to_owned())
+        .collect();
+
+    let (t_localizer, t_chain) = (localizer.clone(), chain.clone());
+    tera.register_function(
+        "t",
+        move |args: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+            let key = args
+                .get("key")
+                .and_then(|k| k.as_str())
+                .ok_or("t() needs a key")?;
+            let mut fluent_args = FluentArgs::new();
+            for (name, value) in args.iter().filter(|(name, _)| *name != "key") {
+                let value = match value {
+                    tera::Value::Number(n) => FluentValue::from(n.as_f64().unwrap_or_default()),
+                    tera::Value::String(s) => FluentValue::from(s.to_owned()),
+                    other => FluentValue::from(other.to_string()),
+                };
+                fluent_args.set(name.to_owned(), value);
+            }
+            let chain: Vec<&str> = t_chain.iter().map(|l| l.as_str()).collect();
+            let message = t_localizer.translate(&chain, key, Some(&fluent_args));
+            Ok(tera::Value::String(message))
+        },
+    );
+
+    tera.register_filter(
+        "localdate",
+        move |value: &tera::Value, _: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+            let value = value.as_str().ok_or("localdate needs a date string")?;
+            let datetime = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+                .map_err(|e| format!("localdate cannot parse {}: {}", value, e))?;
+            let chain: Vec<&str> = chain.iter().map(|l| l.as_str()).collect();
+            Ok(tera::Value::String(localizer.format_datetime(&chain, &datetime)))
+        },
+    );
+}
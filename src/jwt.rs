@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use jsonwebtoken::{
+    decode, decode_header, encode, get_current_timestamp, Algorithm, DecodingKey, EncodingKey,
+    Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::models::RoleCode;
+
+pub type JwtResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A signing key as written in `ROCKET_AUTH.keys`. HS* keys take a `secret`,
+/// EdDSA keys take PEM encoded `private_key`/`public_key`.
+#[derive(Deserialize)]
+pub struct KeyConfig {
+    pub kid: String,
+    pub alg: Algorithm,
+    pub secret: Option<String>,
+    pub private_key: Option<String>,
+    pub public_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccessClaims {
+    pub sub: String,
+    pub roles: Vec<String>,
+    pub jti: String,
+    pub iat: u64,
+    pub exp: u64,
+    /// The admin acting as `sub` while impersonating (RFC 8693 actor claim).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: String,
+}
+
+struct SigningKey {
+    kid: String,
+    alg: Algorithm,
+    key: EncodingKey,
+}
+
+pub struct JwtKeys {
+    signing: SigningKey,
+    verifying: HashMap<String, (Algorithm, DecodingKey)>,
+    ttl: u64,
+}
+
+impl JwtKeys {
+    /// The first configured key signs new tokens, every key is accepted when
+    /// verifying so that a retired key keeps working until its tokens expire.
+    pub fn new(keys: &[KeyConfig], ttl: u64) -> JwtResult<Self> {
+        let first = keys.first().ok_or("JWT mode needs at least one key")?;
+
+        let mut verifying = HashMap::new();
+        for key in keys {
+            let decoding_key = match key.alg {
+                Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                    DecodingKey::from_secret(Self::secret(key)?.as_bytes())
+                }
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(
+                    key.public_key.as_ref().ok_or("EdDSA keys need a public_key")?.as_bytes(),
+                )?,
+                _ => return Err(format!("Unsupported JWT algorithm for key {}", key.kid).into()),
+            };
+            verifying.insert(key.kid.to_owned(), (key.alg, decoding_key));
+        }
+
+        let encoding_key = match first.alg {
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(
+                first.private_key.as_ref().ok_or("EdDSA keys need a private_key")?.as_bytes(),
+            )?,
+            _ => EncodingKey::from_secret(Self::secret(first)?.as_bytes()),
+        };
+
+        Ok(JwtKeys {
+            signing: SigningKey {
+                kid: first.kid.to_owned(),
+                alg: first.alg,
+                key: encoding_key,
+            },
+            verifying,
+            ttl,
+        })
+    }
+
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    fn secret(key: &KeyConfig) -> JwtResult<&String> {
+        key.secret
+            .as_ref()
+            .ok_or_else(|| format!("Key {} needs a secret", key.kid).into())
+    }
+
+    pub fn issue(
+        &self,
+        user_id: i32,
+        roles: &[RoleCode],
+        jti: String,
+        impersonator_id: Option<i32>,
+    ) -> JwtResult<String> {
+        let now = get_current_timestamp();
+        let claims = AccessClaims {
+            sub: user_id.to_string(),
+            roles: roles.iter().map(|r| r.as_str().to_owned()).collect(),
+            jti,
+            iat: now,
+            exp: now + self.ttl,
+            act: impersonator_id.map(|id| Actor { sub: id.to_string() }),
+        };
+
+        let mut header = Header::new(self.signing.alg);
+        header.kid = Some(self.signing.kid.to_owned());
+        Ok(encode(&header, &claims, &self.signing.key)?)
+    }
+
+    pub fn verify(&self, token: &str) -> JwtResult<AccessClaims> {
+        let kid = decode_header(token)?.kid.ok_or("Token has no kid")?;
+        let (alg, key) = self.verifying.get(&kid).ok_or("Token signed with an unknown key")?;
+
+        let mut validation = Validation::new(*alg);
+        validation.leeway = 0;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        Ok(decode::<AccessClaims>(token, key, &validation)?.claims)
+    }
+}
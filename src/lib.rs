@@ -4,9 +4,16 @@ extern crate diesel;
 extern crate rocket;
 
 mod auth;
+mod catalog;
 pub mod commands;
-mod mail;
+pub mod i18n;
+mod jwt;
+pub mod mail;
+pub mod mail_queue;
 mod models;
+mod oidc;
 mod repositories;
 pub mod rocket_routes;
+mod scheduler;
 mod schema;
+pub mod webhooks;
@@ -1,32 +1,215 @@
-use lettre::message::header::ContentType;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::transport::smtp::response::Response;
-use lettre::Transport;
-use lettre::{Message, SmtpTransport};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lettre::message::header::{ContentType, Header, HeaderName, HeaderValue};
+use lettre::message::MultiPart;
+use lettre::{Message, SmtpTransport, Transport};
 use tera::Context;
+
+use crate::auth;
+use crate::i18n::{self, Localizer};
+
+/// A rendered email, independent of how it ends up being delivered.
+#[derive(Clone, Debug)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: Option<String>,
+    pub list_unsubscribe: Option<String>,
+}
+
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(ListUnsubscribe(s.to_owned()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.to_owned())
+    }
+}
+
+/// RFC 8058 one-click unsubscribe, only meaningful with an https link.
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_s: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(ListUnsubscribePost)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "List-Unsubscribe=One-Click".to_owned())
+    }
+}
+
+impl Email {
+    pub fn to_message(&self) -> Result<Message, Box<dyn Error>> {
+        let mut builder = Message::builder()
+            .subject(self.subject.to_owned())
+            .from(self.from.parse()?)
+            .to(self.to.parse()?);
+        if let Some(list_unsubscribe) = &self.list_unsubscribe {
+            builder = builder.header(ListUnsubscribe(list_unsubscribe.to_owned()));
+            if list_unsubscribe.contains("<https://") {
+                builder = builder.header(ListUnsubscribePost);
+            }
+        }
+
+        let message = match &self.text_body {
+            Some(text_body) => builder.multipart(MultiPart::alternative_plain_html(
+                text_body.to_owned(),
+                self.html_body.to_owned(),
+            ))?,
+            None => builder
+                .header(ContentType::TEXT_HTML)
+                .body(self.html_body.to_owned())?,
+        };
+        Ok(message)
+    }
+}
+
+pub trait MailTransport {
+    fn send(&self, email: &Email) -> Result<(), Box<dyn Error>>;
+}
+
+pub struct SmtpMailTransport {
+    pub transport: SmtpTransport,
+}
+
+impl MailTransport for SmtpMailTransport {
+    fn send(&self, email: &Email) -> Result<(), Box<dyn Error>> {
+        self.transport.send(&email.to_message()?)?;
+        Ok(())
+    }
+}
+
+/// Delivers into a maildir: written under `tmp/`, then moved into `new/`.
+pub struct FileMailTransport {
+    pub dir: PathBuf,
+}
+
+impl MailTransport for FileMailTransport {
+    fn send(&self, email: &Email) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(self.dir.join("tmp"))?;
+        fs::create_dir_all(self.dir.join("new"))?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let file_name = format!("{}-{}.eml", timestamp, auth::random_token(8));
+        let tmp_path = self.dir.join("tmp").join(&file_name);
+        fs::write(&tmp_path, email.to_message()?.formatted())?;
+        fs::rename(tmp_path, self.dir.join("new").join(file_name))?;
+        Ok(())
+    }
+}
+
+pub struct StdoutMailTransport;
+
+impl MailTransport for StdoutMailTransport {
+    fn send(&self, email: &Email) -> Result<(), Box<dyn Error>> {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(&email.to_message()?.formatted())?;
+        stdout.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Keeps sent emails in memory; clones share the same outbox.
+#[derive(Clone, Default)]
+pub struct MemoryMailTransport {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl MemoryMailTransport {
+    pub fn messages(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl MailTransport for MemoryMailTransport {
+    fn send(&self, email: &Email) -> Result<(), Box<dyn Error>> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
 pub struct HtmlMailer {
-    pub credentials: Credentials,
-    pub smtp_host: String,
     pub template_engine: tera::Tera,
+    pub transport: Box<dyn MailTransport>,
+    /// `from`, `subject` and `list_unsubscribe` are Tera templates rendered
+    /// with the message context, which also holds the recipient as `to`.
+    pub from: String,
+    pub subject: String,
+    pub list_unsubscribe: Option<String>,
+    /// Makes `t()` and `localdate` available to the templates.
+    pub localizer: Option<Arc<Localizer>>,
 }
 
 impl HtmlMailer {
+    /// Renders `<template_name>.html`, plus `<template_name>.txt` as the plain
+    /// text alternative when that template exists, in the recipient's locale.
+    pub fn render(
+        &self,
+        to: &String,
+        locale: Option<&str>,
+        template_name: &str,
+        context: &Context,
+    ) -> Result<Email, Box<dyn Error>> {
+        let mut context = context.clone();
+        context.insert("to", to);
+
+        let mut tera = self.template_engine.clone();
+        if let Some(localizer) = &self.localizer {
+            let chain = localizer.fallback_chain(locale);
+            context.insert("locale", chain[0]);
+            i18n::register(&mut tera, localizer.clone(), locale);
+        }
+
+        let html_body = tera.render(&format!("{}.html", template_name), &context)?;
+        let text_template = format!("{}.txt", template_name);
+        let text_body = match tera.get_template_names().any(|name| name == text_template) {
+            true => Some(tera.render(&text_template, &context)?),
+            false => None,
+        };
+        let list_unsubscribe = match &self.list_unsubscribe {
+            Some(template) => Some(tera.render_str(template, &context)?),
+            None => None,
+        };
+
+        Ok(Email {
+            from: tera.render_str(&self.from, &context)?,
+            to: to.to_owned(),
+            subject: tera.render_str(&self.subject, &context)?,
+            html_body,
+            text_body,
+            list_unsubscribe,
+        })
+    }
+
     pub fn send(
-        self,
+        &self,
         to: &String,
+        locale: Option<&str>,
         template_name: &str,
         context: &Context,
-    ) -> Result<Response, Box<dyn std::error::Error>> {
-        let html_body = self.template_engine.render(template_name, &context)?;
-        let message = Message::builder()
-            .subject("Cr8s digest")
-            .from("Cr8s <info@cr8s.com>".parse()?)
-            .to(to.parse()?)
-            .header(ContentType::TEXT_HTML)
-            .body(html_body)?;
-        let mailer = SmtpTransport::relay(&self.smtp_host)?
-            .credentials(self.credentials)
-            .build();
-        mailer.send(&message).map_err(|e| e.into())
+    ) -> Result<(), Box<dyn Error>> {
+        let email = self.render(to, locale, template_name, context)?;
+        self.transport.send(&email)
     }
 }
@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{PgConnection, QueryResult};
+use rand::Rng;
+
+use crate::mail::{Email, MailTransport};
+use crate::models::{NewOutboundEmail, OutboundEmail};
+use crate::repositories::OutboundEmailRepository;
+
+/// Attempts before a failing email is declared dead.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// Stores emails in `outbound_emails`, the queue worker delivers them.
+pub struct QueueMailTransport {
+    pub connection: PgConnection,
+}
+
+impl MailTransport for QueueMailTransport {
+    fn send(&self, email: &Email) -> Result<(), Box<dyn Error>> {
+        // Refuse what could never be delivered rather than queueing it.
+        email.to_message()?;
+
+        let new_email = NewOutboundEmail {
+            from_address: email.from.to_owned(),
+            to_address: email.to.to_owned(),
+            subject: email.subject.to_owned(),
+            html_body: email.html_body.to_owned(),
+            text_body: email.text_body.to_owned(),
+            list_unsubscribe: email.list_unsubscribe.to_owned(),
+        };
+        OutboundEmailRepository::create(&self.connection, new_email)?;
+        Ok(())
+    }
+}
+
+impl From<&OutboundEmail> for Email {
+    fn from(email: &OutboundEmail) -> Self {
+        Email {
+            from: email.from_address.to_owned(),
+            to: email.to_address.to_owned(),
+            subject: email.subject.to_owned(),
+            html_body: email.html_body.to_owned(),
+            text_body: email.text_body.to_owned(),
+            list_unsubscribe: email.list_unsubscribe.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Delivery {
+    pub sent: usize,
+    pub retried: usize,
+    pub dead: usize,
+}
+
+/// A minute after the first failure, doubling up to six hours, plus up to
+/// 10% jitter so a batch that failed together doesn't retry together.
+pub fn retry_delay(attempts: i32) -> Duration {
+    let seconds = 60i64
+        .saturating_mul(1 << (attempts - 1).clamp(0, 16))
+        .min(6 * 60 * 60);
+    Duration::seconds(seconds + rand::thread_rng().gen_range(0..=seconds / 10))
+}
+
+/// When to try again after failed attempt number `attempts`, or `None` once
+/// the delivery is dead: the failure was `permanent` or attempts ran out.
+pub fn next_attempt(attempts: i32, permanent: bool) -> Option<NaiveDateTime> {
+    match permanent || attempts >= MAX_ATTEMPTS {
+        true => None,
+        false => Some(Utc::now().naive_utc() + retry_delay(attempts)),
+    }
+}
+
+/// Logs a failed delivery of `what`, such as `Email 3 to a@example.com`,
+/// with the `retry_at` from `next_attempt`.
+pub fn log_failure(
+    what: &str,
+    attempts: i32,
+    retry_at: Option<NaiveDateTime>,
+    error: &dyn Display,
+) {
+    match retry_at {
+        Some(retry_at) => log::warn!("{} failed, retrying at {}: {}", what, retry_at, error),
+        None => log::error!("{} is dead after {} attempts: {}", what, attempts, error),
+    }
+}
+
+/// Failures that retrying cannot fix: a 5xx from the SMTP server, or an
+/// address or message that cannot be built in the first place.
+pub fn is_permanent(error: &(dyn Error + 'static)) -> bool {
+    if let Some(e) = error.downcast_ref::<lettre::transport::smtp::Error>() {
+        return e.is_permanent();
+    }
+    error.is::<lettre::address::AddressError>() || error.is::<lettre::error::Error>()
+}
+
+/// Sends up to `limit` due emails through `transport`.
+pub fn deliver_due(
+    c: &PgConnection,
+    transport: &dyn MailTransport,
+    limit: i64,
+) -> QueryResult<Delivery> {
+    let lease_until = Utc::now().naive_utc() + Duration::minutes(10);
+    let emails = OutboundEmailRepository::claim_due(c, limit, lease_until)?;
+
+    let mut delivery = Delivery::default();
+    for email in &emails {
+        let error = match transport.send(&email.into()) {
+            Ok(()) => {
+                OutboundEmailRepository::mark_sent(c, email.id)?;
+                delivery.sent += 1;
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        let attempts = email.attempts + 1;
+        let retry_at = next_attempt(attempts, is_permanent(error.as_ref()));
+        let what = format!("Email {} to {}", email.id, email.to_address);
+        log_failure(&what, attempts, retry_at, &error);
+        match retry_at {
+            Some(_) => delivery.retried += 1,
+            None => delivery.dead += 1,
+        }
+        OutboundEmailRepository::mark_failed(c, email.id, error.to_string(), retry_at)?;
+    }
+
+    Ok(delivery)
+}
@@ -4,37 +4,35 @@ use diesel::{pg::Pg, sql_types::Text};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Queryable, Deserialize, Serialize)]
+#[derive(Queryable, Serialize)]
 pub struct Rustacean {
-    #[serde(skip_deserializing)]
     pub id: i32,
     pub name: String,
     pub email: String,
-    #[serde(skip_deserializing)]
     pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
 }
 
-#[derive(Insertable, Deserialize)]
+#[derive(Insertable)]
 #[table_name = "rustaceans"]
 pub struct NewRustacean {
     pub name: String,
     pub email: String,
 }
 
-#[derive(Queryable, Associations, Serialize, Deserialize)]
+#[derive(Queryable, Associations, Serialize)]
 pub struct Crate {
-    #[serde(skip_deserializing)]
     pub id: i32,
     pub rustacean_id: i32,
     pub code: String,
     pub name: String,
     pub version: String,
     pub description: Option<String>,
-    #[serde(skip_deserializing)]
     pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
 }
 
-#[derive(Insertable, Deserialize)]
+#[derive(Insertable)]
 #[table_name = "crates"]
 pub struct NewCrate {
     pub rustacean_id: i32,
@@ -44,6 +42,142 @@ pub struct NewCrate {
     pub description: Option<String>,
 }
 
+#[derive(Insertable)]
+#[table_name = "digest_runs"]
+pub struct NewDigestRun {
+    pub recipient: String,
+    pub since: NaiveDateTime,
+    pub until: NaiveDateTime,
+}
+
+#[derive(Queryable, Debug)]
+pub struct JobRun {
+    pub id: i32,
+    pub job: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub succeeded: Option<bool>,
+    pub error: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "job_runs"]
+pub struct NewJobRun {
+    pub job: String,
+}
+
+#[derive(Queryable, Debug)]
+pub struct OutboundEmail {
+    pub id: i32,
+    pub from_address: String,
+    pub to_address: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: Option<String>,
+    pub list_unsubscribe: Option<String>,
+    pub status: EmailStatus,
+    pub attempts: i32,
+    /// When a pending email is retried, or when a claimed one may be
+    /// reclaimed because its worker died.
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub sent_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "outbound_emails"]
+pub struct NewOutboundEmail {
+    pub from_address: String,
+    pub to_address: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: Option<String>,
+    pub list_unsubscribe: Option<String>,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// Event names, or `crate.*` style patterns, that are delivered.
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize)]
+#[table_name = "webhooks"]
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+}
+
+#[derive(Queryable, Serialize, Debug)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    /// HTTP status of the last attempt, if the endpoint answered at all.
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "webhook_deliveries"]
+pub struct NewWebhookDelivery {
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: String,
+}
+
+#[derive(Queryable, Serialize)]
+pub struct DigestSubscription {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub rustacean_id: Option<i32>,
+    pub frequency: DigestFrequency,
+    /// Only crates published by this rustacean.
+    pub owner_id: Option<i32>,
+    /// Only crates whose code, name or description contains this keyword.
+    pub keyword: Option<String>,
+    pub last_sent_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    /// Overrides the locale of the subscribed user.
+    pub locale: Option<String>,
+}
+
+#[derive(Insertable, Deserialize)]
+#[table_name = "digest_subscriptions"]
+pub struct NewDigestSubscription {
+    pub user_id: Option<i32>,
+    pub rustacean_id: Option<i32>,
+    #[serde(default)]
+    pub frequency: DigestFrequency,
+    pub owner_id: Option<i32>,
+    pub keyword: Option<String>,
+    pub locale: Option<String>,
+}
+
+#[derive(AsChangeset, Deserialize)]
+#[table_name = "digest_subscriptions"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct DigestSubscriptionChanges {
+    pub frequency: DigestFrequency,
+    pub owner_id: Option<i32>,
+    pub keyword: Option<String>,
+    pub locale: Option<String>,
+}
+
 #[derive(Identifiable, Queryable, Debug, Serialize)]
 pub struct User {
     pub id: i32,
@@ -51,6 +185,12 @@ pub struct User {
     #[serde(skip_serializing)]
     pub password: String,
     pub created_at: NaiveDateTime,
+    pub email: Option<String>,
+    #[serde(skip_serializing)]
+    pub oidc_subject: Option<String>,
+    pub disabled_at: Option<NaiveDateTime>,
+    pub disabled_reason: Option<String>,
+    pub locale: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -58,9 +198,12 @@ pub struct User {
 pub struct NewUser {
     pub username: String,
     pub password: String,
+    pub email: Option<String>,
+    pub oidc_subject: Option<String>,
+    pub locale: Option<String>,
 }
 
-#[derive(Queryable, Debug)]
+#[derive(Queryable, Debug, Serialize)]
 pub struct Role {
     pub id: i32,
     pub code: RoleCode,
@@ -92,8 +235,30 @@ pub struct NewUserRole {
     pub role_id: i32,
 }
 
-#[derive(Debug, FromSqlRow, AsExpression)]
+#[derive(Queryable, Serialize)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub user_id: i32,
+    pub impersonator_id: Option<i32>,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+pub struct NewAuditEntry {
+    pub user_id: i32,
+    pub impersonator_id: Option<i32>,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+}
+
+#[derive(Debug, Clone, FromSqlRow, AsExpression, Serialize, Deserialize)]
 #[sql_type = "diesel::sql_types::Text"]
+#[serde(rename_all = "lowercase")]
 pub enum RoleCode {
     Admin,
     Editor,
@@ -145,3 +310,141 @@ impl diesel::serialize::ToSql<Text, Pg> for RoleCode {
         Ok(diesel::serialize::IsNull::No)
     }
 }
+
+#[derive(Debug, Clone, Copy, Default, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[sql_type = "diesel::sql_types::Text"]
+#[serde(rename_all = "lowercase")]
+pub enum DigestFrequency {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl DigestFrequency {
+    pub fn period(&self) -> chrono::Duration {
+        match self {
+            DigestFrequency::Daily => chrono::Duration::days(1),
+            DigestFrequency::Weekly => chrono::Duration::weeks(1),
+        }
+    }
+}
+
+impl diesel::deserialize::FromSql<Text, Pg> for DigestFrequency {
+    fn from_sql(bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        match bytes {
+            Some(b) => match std::str::from_utf8(b) {
+                Ok("weekly") => Ok(DigestFrequency::Weekly),
+                _ => Ok(DigestFrequency::Daily),
+            },
+            _ => Ok(DigestFrequency::Daily),
+        }
+    }
+}
+
+impl diesel::serialize::ToSql<Text, Pg> for DigestFrequency {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        match *self {
+            DigestFrequency::Daily => out.write_all(b"daily")?,
+            DigestFrequency::Weekly => out.write_all(b"weekly")?,
+        }
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, FromSqlRow, AsExpression)]
+#[sql_type = "diesel::sql_types::Text"]
+pub enum EmailStatus {
+    Pending,
+    Sending,
+    Sent,
+    /// Failed permanently or ran out of attempts.
+    Dead,
+}
+
+impl EmailStatus {
+    pub fn from_string(string: String) -> Result<Self, Box<dyn std::error::Error>> {
+        match string.as_str() {
+            "pending" => Ok(EmailStatus::Pending),
+            "sending" => Ok(EmailStatus::Sending),
+            "sent" => Ok(EmailStatus::Sent),
+            "dead" => Ok(EmailStatus::Dead),
+            _ => Err("Invalid value to transform to email status".into()),
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailStatus::Pending => "pending",
+            EmailStatus::Sending => "sending",
+            EmailStatus::Sent => "sent",
+            EmailStatus::Dead => "dead",
+        }
+    }
+}
+
+impl diesel::deserialize::FromSql<Text, Pg> for EmailStatus {
+    fn from_sql(bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        let string = <String as diesel::deserialize::FromSql<Text, Pg>>::from_sql(bytes)?;
+        EmailStatus::from_string(string).map_err(|e| e.to_string().into())
+    }
+}
+
+impl diesel::serialize::ToSql<Text, Pg> for EmailStatus {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, FromSqlRow, AsExpression, Serialize)]
+#[sql_type = "diesel::sql_types::Text"]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Pending,
+    Sending,
+    Sent,
+    /// Rejected by the endpoint or ran out of attempts.
+    Dead,
+}
+
+impl DeliveryStatus {
+    pub fn from_string(string: String) -> Result<Self, Box<dyn std::error::Error>> {
+        match string.as_str() {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "sending" => Ok(DeliveryStatus::Sending),
+            "sent" => Ok(DeliveryStatus::Sent),
+            "dead" => Ok(DeliveryStatus::Dead),
+            _ => Err("Invalid value to transform to delivery status".into()),
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Sending => "sending",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+}
+
+impl diesel::deserialize::FromSql<Text, Pg> for DeliveryStatus {
+    fn from_sql(bytes: Option<&[u8]>) -> diesel::deserialize::Result<Self> {
+        let string = <String as diesel::deserialize::FromSql<Text, Pg>>::from_sql(bytes)?;
+        DeliveryStatus::from_string(string).map_err(|e| e.to_string().into())
+    }
+}
+
+impl diesel::serialize::ToSql<Text, Pg> for DeliveryStatus {
+    fn to_sql<W: std::io::Write>(
+        &self,
+        out: &mut diesel::serialize::Output<W, Pg>,
+    ) -> diesel::serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::auth;
+use crate::models::RoleCode;
+
+pub type OidcResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+#[derive(Deserialize, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    /// IdP group name -> cr8s role code, e.g. `{ "cr8s-editors" = "editor" }`.
+    #[serde(default)]
+    pub group_roles: HashMap<String, String>,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".to_owned(), "email".to_owned(), "profile".to_owned()]
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_owned()
+}
+
+#[derive(Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+/// Everything the callback needs to finish a login started by `/auth/oidc/login`.
+#[derive(Serialize, Deserialize)]
+pub struct AuthorizationRequest {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl AuthorizationRequest {
+    pub fn new() -> Self {
+        AuthorizationRequest {
+            state: auth::random_token(32),
+            nonce: auth::random_token(32),
+            code_verifier: auth::random_token(64),
+        }
+    }
+
+    pub fn authorize_url(&self, config: &OidcConfig, metadata: &ProviderMetadata) -> OidcResult<Url> {
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &config.client_id)
+            .append_pair("redirect_uri", &config.redirect_url)
+            .append_pair("scope", &config.scopes.join(" "))
+            .append_pair("state", &self.state)
+            .append_pair("nonce", &self.nonce)
+            .append_pair("code_challenge", &code_challenge(&self.code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url)
+    }
+}
+
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+pub async fn discover(config: &OidcConfig) -> OidcResult<ProviderMetadata> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        config.issuer.trim_end_matches('/')
+    );
+    let metadata = reqwest::get(url)
+        .await?
+        .error_for_status()?
+        .json::<ProviderMetadata>()
+        .await?;
+    if metadata.issuer != config.issuer {
+        return Err("Discovered issuer does not match the configured one".into());
+    }
+    Ok(metadata)
+}
+
+pub async fn exchange_code(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+) -> OidcResult<String> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.redirect_url.as_str()),
+        ("client_id", config.client_id.as_str()),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(client_secret) = &config.client_secret {
+        form.push(("client_secret", client_secret.as_str()));
+    }
+
+    let response = reqwest::Client::new()
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TokenResponse>()
+        .await?;
+    Ok(response.id_token)
+}
+
+pub async fn validate_id_token(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+) -> OidcResult<IdTokenClaims> {
+    let header = decode_header(id_token)?;
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err("ID tokens must be signed with the issuer's public key".into());
+    }
+
+    let jwks = reqwest::get(&metadata.jwks_uri)
+        .await?
+        .error_for_status()?
+        .json::<JwkSet>()
+        .await?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or("No matching key in the issuer's JWKS")?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&config.issuer]);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err("ID token nonce does not match the login request".into());
+    }
+    Ok(claims)
+}
+
+pub fn map_roles(config: &OidcConfig, claims: &IdTokenClaims) -> Vec<RoleCode> {
+    let groups = match claims.extra.get(&config.groups_claim) {
+        Some(Value::Array(groups)) => groups.iter().filter_map(|g| g.as_str()).collect(),
+        Some(Value::String(group)) => vec![group.as_str()],
+        _ => vec![],
+    };
+
+    let mut role_codes: Vec<RoleCode> = vec![];
+    for group in groups {
+        if let Some(code) = config.group_roles.get(group) {
+            match RoleCode::from_string(code.to_owned()) {
+                Ok(role_code) if !role_codes.iter().any(|r| r.as_str() == role_code.as_str()) => {
+                    role_codes.push(role_code)
+                }
+                Ok(_) => {}
+                Err(_) => log::warn!("Ignoring unknown role code {} for group {}", code, group),
+            }
+        }
+    }
+    if role_codes.is_empty() {
+        role_codes.push(RoleCode::Viewer);
+    }
+    role_codes
+}
@@ -1,7 +1,9 @@
 use crate::models::*;
 use crate::schema::*;
+use chrono::NaiveDateTime;
 use diesel::dsl::now;
 use diesel::dsl::IntervalDsl;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::PgConnection;
 
@@ -19,13 +21,120 @@ impl RustaceanRepository {
         rustaceans::table.find(id).get_result::<Rustacean>(c)
     }
 
+    /// Like `find_multiple`, each with their crates, in a single query.
+    pub fn find_multiple_with_crates(
+        c: &PgConnection,
+        limit: i64,
+    ) -> QueryResult<Vec<(Rustacean, Vec<Crate>)>> {
+        let newest = rustaceans::table
+            .select(rustaceans::id)
+            .order(rustaceans::id.desc())
+            .limit(limit);
+        let rows = rustaceans::table
+            .left_join(crates::table)
+            .filter(rustaceans::id.eq_any(newest))
+            .order((rustaceans::id.desc(), crates::id.desc()))
+            .load::<(Rustacean, Option<Crate>)>(c)?;
+        Ok(Self::with_crates(rows))
+    }
+
+    /// Like `find`, with the rustacean's crates, in a single query.
+    pub fn find_with_crates(c: &PgConnection, id: i32) -> QueryResult<(Rustacean, Vec<Crate>)> {
+        let rows = rustaceans::table
+            .left_join(crates::table)
+            .filter(rustaceans::id.eq(id))
+            .order(crates::id.desc())
+            .load::<(Rustacean, Option<Crate>)>(c)?;
+        Self::with_crates(rows)
+            .into_iter()
+            .next()
+            .ok_or(diesel::result::Error::NotFound)
+    }
+
+    /// Folds the rows of a left join, sorted by rustacean, into one entry per
+    /// rustacean.
+    fn with_crates(rows: Vec<(Rustacean, Option<Crate>)>) -> Vec<(Rustacean, Vec<Crate>)> {
+        let mut grouped: Vec<(Rustacean, Vec<Crate>)> = vec![];
+        for (rustacean, a_crate) in rows {
+            match grouped.last_mut() {
+                Some((last, crates)) if last.id == rustacean.id => crates.extend(a_crate),
+                _ => grouped.push((rustacean, a_crate.into_iter().collect())),
+            }
+        }
+        grouped
+    }
+
+    /// Locks the row until the end of the transaction, for read-modify-write.
+    pub fn find_for_update(c: &PgConnection, id: i32) -> QueryResult<Rustacean> {
+        rustaceans::table
+            .find(id)
+            .for_update()
+            .get_result::<Rustacean>(c)
+    }
+
+    /// Up to `limit` rustaceans after the id `after`, oldest first, to walk
+    /// the whole table a page at a time.
+    pub fn find_page(c: &PgConnection, after: i32, limit: i64) -> QueryResult<Vec<Rustacean>> {
+        rustaceans::table
+            .filter(rustaceans::id.gt(after))
+            .order(rustaceans::id)
+            .limit(limit)
+            .load::<Rustacean>(c)
+    }
+
+    pub fn find_by_emails(c: &PgConnection, emails: Vec<String>) -> QueryResult<Vec<Rustacean>> {
+        rustaceans::table
+            .filter(rustaceans::email.eq_any(emails))
+            .order(rustaceans::id)
+            .load::<Rustacean>(c)
+    }
+
+    pub fn find_by_ids(c: &PgConnection, ids: Vec<i32>) -> QueryResult<Vec<Rustacean>> {
+        rustaceans::table
+            .filter(rustaceans::id.eq_any(ids))
+            .load::<Rustacean>(c)
+    }
+
+    /// The ids among `ids` that exist, locked `FOR SHARE` until the end of
+    /// the transaction, so that none is deleted while crates are written for
+    /// it.
+    pub fn find_ids_for_share(c: &PgConnection, ids: Vec<i32>) -> QueryResult<Vec<i32>> {
+        rustaceans::table
+            .select(rustaceans::id)
+            .filter(rustaceans::id.eq_any(ids))
+            .for_share()
+            .load::<i32>(c)
+    }
+
+    /// The ids among `ids` that exist, locked like `find_for_update`.
+    pub fn find_ids_for_update(c: &PgConnection, ids: Vec<i32>) -> QueryResult<Vec<i32>> {
+        rustaceans::table
+            .select(rustaceans::id)
+            .filter(rustaceans::id.eq_any(ids))
+            .for_update()
+            .load::<i32>(c)
+    }
+
     pub fn create(c: &PgConnection, new_rustacean: NewRustacean) -> QueryResult<Rustacean> {
         diesel::insert_into(rustaceans::table)
             .values(new_rustacean)
             .get_result(c)
     }
 
-    pub fn save(c: &PgConnection, id: i32, rustacean: Rustacean) -> QueryResult<Rustacean> {
+    /// One multi-row insert, returning the rustaceans in the same order.
+    pub fn create_multiple(
+        c: &PgConnection,
+        new_rustaceans: Vec<NewRustacean>,
+    ) -> QueryResult<Vec<Rustacean>> {
+        if new_rustaceans.is_empty() {
+            return Ok(vec![]);
+        }
+        diesel::insert_into(rustaceans::table)
+            .values(&new_rustaceans)
+            .get_results(c)
+    }
+
+    pub fn save(c: &PgConnection, id: i32, rustacean: NewRustacean) -> QueryResult<Rustacean> {
         diesel::update(rustaceans::table.find(id))
             .set((
                 rustaceans::email.eq(rustacean.email.to_owned()),
@@ -44,9 +153,37 @@ impl RustaceanRepository {
 pub struct CrateRepository;
 
 impl CrateRepository {
+    fn since_query(hours_since: i32) -> crates::BoxedQuery<'static, Pg> {
+        crates::table
+            .filter(crates::created_at.ge(now - hours_since.hours()))
+            .order(crates::id.desc())
+            .into_boxed()
+    }
+
     pub fn find_since(c: &PgConnection, hours_since: i32) -> QueryResult<Vec<Crate>> {
+        Self::since_query(hours_since).load::<Crate>(c)
+    }
+
+    pub fn find_since_by_rustacean(
+        c: &PgConnection,
+        rustacean_id: i32,
+        hours_since: i32,
+    ) -> QueryResult<Vec<Crate>> {
+        Self::since_query(hours_since)
+            .filter(crates::rustacean_id.eq(rustacean_id))
+            .load::<Crate>(c)
+    }
+
+    /// Crates created in `[since, until)`, so back to back windows never
+    /// overlap or leave a gap.
+    pub fn find_between(
+        c: &PgConnection,
+        since: NaiveDateTime,
+        until: NaiveDateTime,
+    ) -> QueryResult<Vec<Crate>> {
         crates::table
-            .filter(crates::created_at.ge(now - hours_since.seconds()))
+            .filter(crates::created_at.ge(since))
+            .filter(crates::created_at.lt(until))
             .order(crates::id.desc())
             .load::<Crate>(c)
     }
@@ -62,13 +199,74 @@ impl CrateRepository {
         crates::table.find(id).get_result::<Crate>(c)
     }
 
+    /// Like `find_multiple`, each with its rustacean, in a single query.
+    pub fn find_multiple_with_rustacean(
+        c: &PgConnection,
+        limit: i64,
+    ) -> QueryResult<Vec<(Crate, Rustacean)>> {
+        crates::table
+            .inner_join(rustaceans::table)
+            .limit(limit)
+            .order(crates::id.desc())
+            .load::<(Crate, Rustacean)>(c)
+    }
+
+    /// Like `find`, with the crate's rustacean, in a single query.
+    pub fn find_with_rustacean(c: &PgConnection, id: i32) -> QueryResult<(Crate, Rustacean)> {
+        crates::table
+            .inner_join(rustaceans::table)
+            .filter(crates::id.eq(id))
+            .get_result::<(Crate, Rustacean)>(c)
+    }
+
+    /// Locks the row until the end of the transaction, for read-modify-write.
+    pub fn find_for_update(c: &PgConnection, id: i32) -> QueryResult<Crate> {
+        crates::table.find(id).for_update().get_result::<Crate>(c)
+    }
+
+    /// The ids among `ids` that exist, locked like `find_for_update`.
+    pub fn find_ids_for_update(c: &PgConnection, ids: Vec<i32>) -> QueryResult<Vec<i32>> {
+        crates::table
+            .select(crates::id)
+            .filter(crates::id.eq_any(ids))
+            .for_update()
+            .load::<i32>(c)
+    }
+
+    /// Up to `limit` crates after the id `after`, oldest first, to walk the
+    /// whole table a page at a time.
+    pub fn find_page(c: &PgConnection, after: i32, limit: i64) -> QueryResult<Vec<Crate>> {
+        crates::table
+            .filter(crates::id.gt(after))
+            .order(crates::id)
+            .limit(limit)
+            .load::<Crate>(c)
+    }
+
+    pub fn find_by_codes(c: &PgConnection, codes: Vec<String>) -> QueryResult<Vec<Crate>> {
+        crates::table
+            .filter(crates::code.eq_any(codes))
+            .order(crates::id)
+            .load::<Crate>(c)
+    }
+
     pub fn create(c: &PgConnection, new_crate: NewCrate) -> QueryResult<Crate> {
         diesel::insert_into(crates::table)
             .values(new_crate)
             .get_result(c)
     }
 
-    pub fn save(c: &PgConnection, id: i32, update_crate: Crate) -> QueryResult<Crate> {
+    /// One multi-row insert, returning the crates in the same order.
+    pub fn create_multiple(c: &PgConnection, new_crates: Vec<NewCrate>) -> QueryResult<Vec<Crate>> {
+        if new_crates.is_empty() {
+            return Ok(vec![]);
+        }
+        diesel::insert_into(crates::table)
+            .values(&new_crates)
+            .get_results(c)
+    }
+
+    pub fn save(c: &PgConnection, id: i32, update_crate: NewCrate) -> QueryResult<Crate> {
         diesel::update(crates::table.find(id))
             .set((
                 crates::code.eq(update_crate.code.to_owned()),
@@ -85,6 +283,13 @@ impl CrateRepository {
     pub fn delete(c: &PgConnection, id: i32) -> QueryResult<usize> {
         diesel::delete(crates::table.find(id)).execute(c)
     }
+
+    /// Returns the ids that were deleted.
+    pub fn delete_multiple(c: &PgConnection, ids: Vec<i32>) -> QueryResult<Vec<i32>> {
+        diesel::delete(crates::table.filter(crates::id.eq_any(ids)))
+            .returning(crates::id)
+            .get_results(c)
+    }
 }
 
 pub struct UserRepository;
@@ -109,6 +314,18 @@ impl UserRepository {
             .get_result::<User>(c)
     }
 
+    pub fn find_by_email(c: &PgConnection, email: &String) -> QueryResult<User> {
+        users::table
+            .filter(users::email.eq(email))
+            .get_result::<User>(c)
+    }
+
+    pub fn find_by_oidc_subject(c: &PgConnection, subject: &String) -> QueryResult<User> {
+        users::table
+            .filter(users::oidc_subject.eq(subject))
+            .get_result::<User>(c)
+    }
+
     pub fn create(
         c: &PgConnection,
         new_user: NewUser,
@@ -118,6 +335,42 @@ impl UserRepository {
             .values(new_user)
             .get_result::<User>(c)?;
 
+        Self::add_roles(c, &user, role_codes)?;
+
+        Ok(user)
+    }
+
+    pub fn link_oidc_subject(c: &PgConnection, id: i32, subject: &String) -> QueryResult<User> {
+        diesel::update(users::table.find(id))
+            .set(users::oidc_subject.eq(subject))
+            .get_result::<User>(c)
+    }
+
+    pub fn suspend(c: &PgConnection, id: i32, reason: Option<String>) -> QueryResult<User> {
+        diesel::update(users::table.find(id))
+            .set((
+                users::disabled_at.eq(Some(chrono::Utc::now().naive_utc())),
+                users::disabled_reason.eq(reason),
+            ))
+            .get_result::<User>(c)
+    }
+
+    pub fn reinstate(c: &PgConnection, id: i32) -> QueryResult<User> {
+        diesel::update(users::table.find(id))
+            .set((
+                users::disabled_at.eq(None::<NaiveDateTime>),
+                users::disabled_reason.eq(None::<String>),
+            ))
+            .get_result::<User>(c)
+    }
+
+    pub fn set_roles(c: &PgConnection, user: &User, role_codes: Vec<RoleCode>) -> QueryResult<()> {
+        diesel::delete(users_roles::table.filter(users_roles::user_id.eq(user.id))).execute(c)?;
+
+        Self::add_roles(c, user, role_codes)
+    }
+
+    fn add_roles(c: &PgConnection, user: &User, role_codes: Vec<RoleCode>) -> QueryResult<()> {
         for role_code in role_codes {
             let new_user_role = {
                 if let Ok(role) = RoleRepository::find_by_code(&c, &role_code) {
@@ -144,7 +397,28 @@ impl UserRepository {
                 .execute(c)?;
         }
 
-        Ok(user)
+        Ok(())
+    }
+
+    pub fn add_role(c: &PgConnection, user: &User, role_code: RoleCode) -> QueryResult<()> {
+        let has_role = RoleRepository::find_by_user(c, user)?
+            .iter()
+            .any(|r| r.code.as_str() == role_code.as_str());
+        if has_role {
+            return Ok(());
+        }
+
+        Self::add_roles(c, user, vec![role_code])
+    }
+
+    pub fn remove_role(c: &PgConnection, user: &User, role_code: &RoleCode) -> QueryResult<usize> {
+        let role = RoleRepository::find_by_code(c, role_code)?;
+        diesel::delete(
+            users_roles::table
+                .filter(users_roles::user_id.eq(user.id))
+                .filter(users_roles::role_id.eq(role.id)),
+        )
+        .execute(c)
     }
 
     pub fn delete(c: &PgConnection, id: i32) -> QueryResult<usize> {
@@ -169,6 +443,14 @@ impl RoleRepository {
         )
     }
 
+    pub fn find_by_user_id(c: &PgConnection, user_id: i32) -> QueryResult<Vec<Role>> {
+        roles::table
+            .inner_join(users_roles::table)
+            .filter(users_roles::user_id.eq(user_id))
+            .select(roles::all_columns)
+            .load::<Role>(c)
+    }
+
     pub fn find_by_ids(c: &PgConnection, ids: Vec<i32>) -> QueryResult<Vec<Role>> {
         roles::table.filter(roles::id.eq_any(ids)).get_results(c)
     }
@@ -185,3 +467,436 @@ impl RoleRepository {
             .get_result(c)
     }
 }
+
+pub struct AuditRepository;
+
+impl AuditRepository {
+    pub fn find_multiple(c: &PgConnection, limit: i64) -> QueryResult<Vec<AuditEntry>> {
+        audit_log::table
+            .limit(limit)
+            .order(audit_log::id.desc())
+            .load::<AuditEntry>(c)
+    }
+
+    pub fn create(c: &PgConnection, new_entry: NewAuditEntry) -> QueryResult<AuditEntry> {
+        diesel::insert_into(audit_log::table)
+            .values(new_entry)
+            .get_result(c)
+    }
+
+    pub fn delete_before(c: &PgConnection, cutoff: NaiveDateTime) -> QueryResult<usize> {
+        diesel::delete(audit_log::table.filter(audit_log::created_at.lt(cutoff))).execute(c)
+    }
+}
+
+pub struct DigestRunRepository;
+
+impl DigestRunRepository {
+    /// Where the latest digest sent to `recipient` ended.
+    pub fn find_last_until(
+        c: &PgConnection,
+        recipient: &String,
+    ) -> QueryResult<Option<NaiveDateTime>> {
+        digest_runs::table
+            .select(digest_runs::until)
+            .filter(digest_runs::recipient.eq(recipient))
+            .order(digest_runs::until.desc())
+            .first::<NaiveDateTime>(c)
+            .optional()
+    }
+
+    pub fn create(c: &PgConnection, new_run: NewDigestRun) -> QueryResult<usize> {
+        diesel::insert_into(digest_runs::table)
+            .values(new_run)
+            .execute(c)
+    }
+
+    /// Keeps each recipient's latest run however old, as `find_last_until` starts
+    /// the next digest where it ended.
+    pub fn delete_before(c: &PgConnection, cutoff: NaiveDateTime) -> QueryResult<usize> {
+        let latest = digest_runs::table
+            .select(digest_runs::id)
+            .distinct_on(digest_runs::recipient)
+            .order((
+                digest_runs::recipient,
+                digest_runs::until.desc(),
+                digest_runs::id.desc(),
+            ));
+        diesel::delete(
+            digest_runs::table
+                .filter(digest_runs::created_at.lt(cutoff))
+                .filter(digest_runs::id.ne_all(latest)),
+        )
+        .execute(c)
+    }
+}
+
+/// A subscription with the user or rustacean it belongs to.
+pub type SubscriptionWithSubscriber = (DigestSubscription, Option<User>, Option<Rustacean>);
+
+pub struct DigestSubscriptionRepository;
+
+impl DigestSubscriptionRepository {
+    pub fn find_multiple(c: &PgConnection, limit: i64) -> QueryResult<Vec<DigestSubscription>> {
+        digest_subscriptions::table
+            .limit(limit)
+            .order(digest_subscriptions::id.desc())
+            .load::<DigestSubscription>(c)
+    }
+
+    pub fn find_by_user_id(c: &PgConnection, user_id: i32) -> QueryResult<Vec<DigestSubscription>> {
+        digest_subscriptions::table
+            .filter(digest_subscriptions::user_id.eq(user_id))
+            .order(digest_subscriptions::id.desc())
+            .load::<DigestSubscription>(c)
+    }
+
+    pub fn find(c: &PgConnection, id: i32) -> QueryResult<DigestSubscription> {
+        digest_subscriptions::table
+            .find(id)
+            .get_result::<DigestSubscription>(c)
+    }
+
+    /// Every subscription with the user or rustacean it belongs to, which is
+    /// where the recipient address comes from.
+    pub fn find_with_subscribers(
+        c: &PgConnection,
+    ) -> QueryResult<Vec<SubscriptionWithSubscriber>> {
+        digest_subscriptions::table
+            .left_join(users::table)
+            .left_join(rustaceans::table)
+            .order(digest_subscriptions::id)
+            .load::<SubscriptionWithSubscriber>(c)
+    }
+
+    pub fn create(
+        c: &PgConnection,
+        new_subscription: NewDigestSubscription,
+    ) -> QueryResult<DigestSubscription> {
+        diesel::insert_into(digest_subscriptions::table)
+            .values(new_subscription)
+            .get_result(c)
+    }
+
+    pub fn save(
+        c: &PgConnection,
+        id: i32,
+        changes: DigestSubscriptionChanges,
+    ) -> QueryResult<DigestSubscription> {
+        diesel::update(digest_subscriptions::table.find(id))
+            .set(changes)
+            .get_result(c)
+    }
+
+    pub fn mark_sent(c: &PgConnection, id: i32, sent_until: NaiveDateTime) -> QueryResult<usize> {
+        diesel::update(digest_subscriptions::table.find(id))
+            .set(digest_subscriptions::last_sent_at.eq(Some(sent_until)))
+            .execute(c)
+    }
+
+    pub fn delete(c: &PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::delete(digest_subscriptions::table.find(id)).execute(c)
+    }
+}
+
+pub struct JobRunRepository;
+
+impl JobRunRepository {
+    pub fn find_last(c: &PgConnection, job: &str) -> QueryResult<Option<JobRun>> {
+        job_runs::table
+            .filter(job_runs::job.eq(job))
+            .order(job_runs::started_at.desc())
+            .first::<JobRun>(c)
+            .optional()
+    }
+
+    pub fn find_multiple(c: &PgConnection, limit: i64) -> QueryResult<Vec<JobRun>> {
+        job_runs::table
+            .limit(limit)
+            .order(job_runs::id.desc())
+            .load::<JobRun>(c)
+    }
+
+    pub fn create(c: &PgConnection, new_run: NewJobRun) -> QueryResult<JobRun> {
+        diesel::insert_into(job_runs::table)
+            .values(new_run)
+            .get_result(c)
+    }
+
+    pub fn finish(c: &PgConnection, id: i32, error: Option<String>) -> QueryResult<JobRun> {
+        diesel::update(job_runs::table.find(id))
+            .set((
+                job_runs::finished_at.eq(Some(chrono::Utc::now().naive_utc())),
+                job_runs::succeeded.eq(Some(error.is_none())),
+                job_runs::error.eq(error),
+            ))
+            .get_result(c)
+    }
+
+    pub fn delete_before(c: &PgConnection, cutoff: NaiveDateTime) -> QueryResult<usize> {
+        diesel::delete(job_runs::table.filter(job_runs::started_at.lt(cutoff))).execute(c)
+    }
+}
+
+pub struct OutboundEmailRepository;
+
+impl OutboundEmailRepository {
+    pub fn find_by_status(
+        c: &PgConnection,
+        status: EmailStatus,
+        limit: i64,
+    ) -> QueryResult<Vec<OutboundEmail>> {
+        outbound_emails::table
+            .filter(outbound_emails::status.eq(status))
+            .limit(limit)
+            .order(outbound_emails::id.desc())
+            .load::<OutboundEmail>(c)
+    }
+
+    pub fn create(c: &PgConnection, new_email: NewOutboundEmail) -> QueryResult<OutboundEmail> {
+        diesel::insert_into(outbound_emails::table)
+            .values(new_email)
+            .get_result(c)
+    }
+
+    /// Marks up to `limit` due emails as `sending` until `lease_until`. Rows
+    /// claimed by another worker are skipped, and claims left behind by a
+    /// worker that died become due again once their lease runs out.
+    pub fn claim_due(
+        c: &PgConnection,
+        limit: i64,
+        lease_until: NaiveDateTime,
+    ) -> QueryResult<Vec<OutboundEmail>> {
+        c.transaction(|| {
+            let ids = outbound_emails::table
+                .select(outbound_emails::id)
+                .filter(
+                    outbound_emails::status
+                        .eq_any(vec![EmailStatus::Pending, EmailStatus::Sending]),
+                )
+                .filter(outbound_emails::next_attempt_at.le(now))
+                .order(outbound_emails::next_attempt_at)
+                .limit(limit)
+                .for_update()
+                .skip_locked()
+                .load::<i32>(c)?;
+
+            diesel::update(outbound_emails::table.filter(outbound_emails::id.eq_any(ids)))
+                .set((
+                    outbound_emails::status.eq(EmailStatus::Sending),
+                    outbound_emails::next_attempt_at.eq(lease_until),
+                ))
+                .get_results::<OutboundEmail>(c)
+        })
+    }
+
+    pub fn mark_sent(c: &PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::update(outbound_emails::table.find(id))
+            .set((
+                outbound_emails::status.eq(EmailStatus::Sent),
+                outbound_emails::attempts.eq(outbound_emails::attempts + 1),
+                outbound_emails::sent_at.eq(Some(chrono::Utc::now().naive_utc())),
+            ))
+            .execute(c)
+    }
+
+    /// Records a failed attempt; without `retry_at` the email is dead.
+    pub fn mark_failed(
+        c: &PgConnection,
+        id: i32,
+        error: String,
+        retry_at: Option<NaiveDateTime>,
+    ) -> QueryResult<usize> {
+        let status = match retry_at {
+            Some(_) => EmailStatus::Pending,
+            None => EmailStatus::Dead,
+        };
+        diesel::update(outbound_emails::table.find(id))
+            .set((
+                outbound_emails::status.eq(status),
+                outbound_emails::attempts.eq(outbound_emails::attempts + 1),
+                outbound_emails::next_attempt_at
+                    .eq(retry_at.unwrap_or(chrono::Utc::now().naive_utc())),
+                outbound_emails::last_error.eq(Some(error)),
+            ))
+            .execute(c)
+    }
+
+    /// Puts a dead email back in the queue with a fresh set of attempts.
+    pub fn retry(c: &PgConnection, id: i32) -> QueryResult<OutboundEmail> {
+        diesel::update(
+            outbound_emails::table
+                .find(id)
+                .filter(outbound_emails::status.eq(EmailStatus::Dead)),
+        )
+        .set((
+            outbound_emails::status.eq(EmailStatus::Pending),
+            outbound_emails::attempts.eq(0),
+            outbound_emails::next_attempt_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .get_result(c)
+    }
+}
+
+pub struct WebhookRepository;
+
+impl WebhookRepository {
+    pub fn find_multiple(c: &PgConnection, limit: i64) -> QueryResult<Vec<Webhook>> {
+        webhooks::table
+            .limit(limit)
+            .order(webhooks::id.desc())
+            .load::<Webhook>(c)
+    }
+
+    pub fn find_active(c: &PgConnection) -> QueryResult<Vec<Webhook>> {
+        webhooks::table
+            .filter(webhooks::active.eq(true))
+            .order(webhooks::id)
+            .load::<Webhook>(c)
+    }
+
+    pub fn find(c: &PgConnection, id: i32) -> QueryResult<Webhook> {
+        webhooks::table.find(id).get_result::<Webhook>(c)
+    }
+
+    pub fn create(c: &PgConnection, new_webhook: NewWebhook) -> QueryResult<Webhook> {
+        diesel::insert_into(webhooks::table)
+            .values(new_webhook)
+            .get_result(c)
+    }
+
+    pub fn delete(c: &PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::delete(webhooks::table.find(id)).execute(c)
+    }
+}
+
+pub struct WebhookDeliveryRepository;
+
+impl WebhookDeliveryRepository {
+    pub fn find_by_webhook_id(
+        c: &PgConnection,
+        webhook_id: i32,
+        limit: i64,
+    ) -> QueryResult<Vec<WebhookDelivery>> {
+        webhook_deliveries::table
+            .filter(webhook_deliveries::webhook_id.eq(webhook_id))
+            .limit(limit)
+            .order(webhook_deliveries::id.desc())
+            .load::<WebhookDelivery>(c)
+    }
+
+    pub fn find(c: &PgConnection, id: i32) -> QueryResult<WebhookDelivery> {
+        webhook_deliveries::table
+            .find(id)
+            .get_result::<WebhookDelivery>(c)
+    }
+
+    pub fn create(
+        c: &PgConnection,
+        new_delivery: NewWebhookDelivery,
+    ) -> QueryResult<WebhookDelivery> {
+        diesel::insert_into(webhook_deliveries::table)
+            .values(new_delivery)
+            .get_result(c)
+    }
+
+    /// Inserts a delivery already claimed until `lease_until`, for callers
+    /// that send it themselves.
+    pub fn create_leased(
+        c: &PgConnection,
+        new_delivery: NewWebhookDelivery,
+        lease_until: NaiveDateTime,
+    ) -> QueryResult<WebhookDelivery> {
+        diesel::insert_into(webhook_deliveries::table)
+            .values((
+                new_delivery,
+                webhook_deliveries::status.eq(DeliveryStatus::Sending),
+                webhook_deliveries::next_attempt_at.eq(lease_until),
+            ))
+            .get_result(c)
+    }
+
+    /// Same lease as `OutboundEmailRepository::claim_due`, with each delivery
+    /// paired with the webhook it goes to.
+    pub fn claim_due(
+        c: &PgConnection,
+        limit: i64,
+        lease_until: NaiveDateTime,
+    ) -> QueryResult<Vec<(WebhookDelivery, Webhook)>> {
+        c.transaction(|| {
+            let ids = webhook_deliveries::table
+                .select(webhook_deliveries::id)
+                .filter(
+                    webhook_deliveries::status
+                        .eq_any(vec![DeliveryStatus::Pending, DeliveryStatus::Sending]),
+                )
+                .filter(webhook_deliveries::next_attempt_at.le(now))
+                .order(webhook_deliveries::next_attempt_at)
+                .limit(limit)
+                .for_update()
+                .skip_locked()
+                .load::<i32>(c)?;
+
+            diesel::update(webhook_deliveries::table.filter(webhook_deliveries::id.eq_any(&ids)))
+                .set((
+                    webhook_deliveries::status.eq(DeliveryStatus::Sending),
+                    webhook_deliveries::next_attempt_at.eq(lease_until),
+                ))
+                .execute(c)?;
+
+            webhook_deliveries::table
+                .inner_join(webhooks::table)
+                .filter(webhook_deliveries::id.eq_any(ids))
+                .order(webhook_deliveries::id)
+                .load::<(WebhookDelivery, Webhook)>(c)
+        })
+    }
+
+    pub fn mark_sent(c: &PgConnection, id: i32, response_status: i32) -> QueryResult<usize> {
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::status.eq(DeliveryStatus::Sent),
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::response_status.eq(Some(response_status)),
+                webhook_deliveries::last_error.eq(None::<String>),
+                webhook_deliveries::delivered_at.eq(Some(chrono::Utc::now().naive_utc())),
+            ))
+            .execute(c)
+    }
+
+    /// Records a failed attempt; without `retry_at` the delivery is dead.
+    pub fn mark_failed(
+        c: &PgConnection,
+        id: i32,
+        response_status: Option<i32>,
+        error: String,
+        retry_at: Option<NaiveDateTime>,
+    ) -> QueryResult<usize> {
+        let status = match retry_at {
+            Some(_) => DeliveryStatus::Pending,
+            None => DeliveryStatus::Dead,
+        };
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::status.eq(status),
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::next_attempt_at
+                    .eq(retry_at.unwrap_or(chrono::Utc::now().naive_utc())),
+                webhook_deliveries::response_status.eq(response_status),
+                webhook_deliveries::last_error.eq(Some(error)),
+            ))
+            .execute(c)
+    }
+
+    pub fn delete_before(c: &PgConnection, cutoff: NaiveDateTime) -> QueryResult<usize> {
+        diesel::delete(
+            webhook_deliveries::table
+                .filter(webhook_deliveries::created_at.lt(cutoff))
+                .filter(webhook_deliveries::status.eq_any(vec![
+                    DeliveryStatus::Sent,
+                    DeliveryStatus::Dead,
+                ])),
+        )
+        .execute(c)
+    }
+}
@@ -0,0 +1,127 @@
+use diesel::QueryResult;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Method, Status};
+use rocket::request::Outcome;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Value};
+use rocket::{Request, Response, State};
+use rocket_db_pools::Connection;
+
+use crate::auth;
+use crate::diesel::result::Error::NotFound;
+use crate::models::{NewAuditEntry, Role, RoleCode, User};
+use crate::repositories::{AuditRepository, RoleRepository, UserRepository};
+use crate::rocket_routes::DbConn;
+
+use super::authorization::{issue_session, revoke_session};
+use super::{server_error, AdminUser, CacheConn, Principal, SessionBackend};
+
+/// Records every mutating request, and every request made while
+/// impersonating, with both the effective user and the impersonating admin.
+pub struct AuditLog;
+
+#[rocket::async_trait]
+impl Fairing for AuditLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Audit log",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        // Only set when an authentication guard ran for this request.
+        let principal = match request.local_cache(|| None::<Principal>) {
+            Some(principal) => principal.clone(),
+            None => return,
+        };
+        let read_only = matches!(
+            request.method(),
+            Method::Get | Method::Head | Method::Options
+        );
+        if read_only && principal.impersonator_id.is_none() {
+            return;
+        }
+
+        let new_entry = NewAuditEntry {
+            user_id: principal.user_id,
+            impersonator_id: principal.impersonator_id,
+            method: request.method().as_str().to_owned(),
+            path: request.uri().path().to_string(),
+            status: response.status().code as i32,
+        };
+        match request.guard::<DbConn>().await {
+            Outcome::Success(db) => {
+                if let Err(e) = db.run(move |c| AuditRepository::create(c, new_entry)).await {
+                    log::error!("Cannot record audit entry: {}", e);
+                }
+            }
+            _ => log::error!("Cannot record audit entry: no database connection"),
+        }
+    }
+}
+
+#[get("/admin/audit")]
+pub async fn get_audit_log(db: DbConn, _user: AdminUser) -> Result<Value, Custom<Value>> {
+    db.run(|c| {
+        AuditRepository::find_multiple(c, 100)
+            .map(|entries| json!(entries))
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+#[post("/admin/impersonate/<user_id>")]
+pub async fn impersonate(
+    db: DbConn,
+    admin: AdminUser,
+    backend: &State<SessionBackend>,
+    mut cache: Connection<CacheConn>,
+    user_id: i32,
+) -> Result<Value, Custom<Value>> {
+    if admin.0.impersonator_id.is_some() {
+        return Err(Custom(
+            Status::BadRequest,
+            json!("End the current impersonation first"),
+        ));
+    }
+
+    let (user, roles) = db
+        .run(move |c| -> QueryResult<(User, Vec<Role>)> {
+            let user = UserRepository::find(c, user_id)?;
+            let roles = RoleRepository::find_by_user(c, &user)?;
+            Ok((user, roles))
+        })
+        .await
+        .map_err(|e| match e {
+            NotFound => Custom(Status::NotFound, json!("User not found")),
+            _ => server_error(&e.into()),
+        })?;
+
+    let role_codes: Vec<RoleCode> = roles.into_iter().map(|r| r.code).collect();
+    log::info!("User {} impersonates user {}", admin.0.user_id, user.id);
+    issue_session(
+        backend,
+        &mut cache,
+        &user,
+        &role_codes,
+        auth::random_token(128),
+        Some(admin.0.user_id),
+    )
+    .await
+}
+
+#[delete("/admin/impersonate")]
+pub async fn end_impersonation(
+    principal: Principal,
+    backend: &State<SessionBackend>,
+    mut cache: Connection<CacheConn>,
+) -> Result<NoContent, Custom<Value>> {
+    if principal.impersonator_id.is_none() {
+        return Err(Custom(Status::BadRequest, json!("Not impersonating")));
+    }
+
+    revoke_session(backend, &mut cache, &principal)
+        .await
+        .map(|_| NoContent)
+}
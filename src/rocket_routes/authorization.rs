@@ -1,42 +1,198 @@
 use rocket::{
+    fairing::AdHoc,
     http::Status,
-    response::status::Custom,
+    response::status::{Custom, NoContent},
     serde::json::{json, Json, Value},
+    State,
 };
 use rocket_db_pools::{deadpool_redis::redis::AsyncCommands, Connection};
 
-use crate::{repositories::UserRepository, auth, models::User};
+use crate::{
+    auth::{self, AuthConfig, AuthMode},
+    jwt::JwtKeys,
+    models::{Role, RoleCode, User},
+    repositories::{RoleRepository, UserRepository},
+};
 
-use super::{server_error, CacheConn, DbConn};
+use super::{server_error, CacheConn, DbConn, Principal, SessionBackend};
 use diesel::result::Error::NotFound;
 
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Auth config", |rocket| async {
+        let config = if rocket.figment().find_value("auth").is_ok() {
+            match rocket.figment().extract_inner::<AuthConfig>("auth") {
+                Ok(config) => config,
+                Err(e) => {
+                    log::error!("Invalid auth configuration: {}", e);
+                    return Err(rocket);
+                }
+            }
+        } else {
+            AuthConfig::default()
+        };
+
+        let backend = match config.mode {
+            AuthMode::Session => SessionBackend::Redis,
+            AuthMode::Jwt => match JwtKeys::new(&config.keys, config.token_ttl) {
+                Ok(keys) => SessionBackend::Jwt(keys),
+                Err(e) => {
+                    log::error!("Invalid JWT keys: {}", e);
+                    return Err(rocket);
+                }
+            },
+        };
+        Ok(rocket.manage(backend))
+    })
+}
+
 #[post("/login", format = "json", data = "<credentials>")]
 pub async fn login(
     db: DbConn,
+    backend: &State<SessionBackend>,
     mut cache: Connection<CacheConn>,
     credentials: Json<auth::Credentials>,
 ) -> Result<Value, Custom<Value>> {
     let username: String = credentials.username.clone();
-    let user: User = db
-        .run(move |c| {
-            UserRepository::find_by_username(&c, &username).map_err(|e| match e {
+    let (user, roles) = db
+        .run(move |c| -> Result<(User, Vec<Role>), Custom<Value>> {
+            let user = UserRepository::find_by_username(c, &username).map_err(|e| match e {
                 NotFound => Custom(Status::Unauthorized, json!("Wrong credentials")),
                 _ => server_error(&e.into()),
-            })
+            })?;
+            let roles =
+                RoleRepository::find_by_user(c, &user).map_err(|e| server_error(&e.into()))?;
+            Ok((user, roles))
         })
         .await?;
 
     let session_id: String = auth::authorize_user(&user, &credentials)
         .map_err(|_| Custom(Status::Unauthorized, json!("Wrong credentials")))?;
 
-    cache
-        .set_ex::<_, _, ()>(format!("sessions/{}", session_id), user.id, 3 * 60 * 60)
+    let role_codes: Vec<RoleCode> = roles.into_iter().map(|r| r.code).collect();
+    issue_session(backend, &mut cache, &user, &role_codes, session_id, None).await
+}
+
+/// Hands out a bearer token for `user`, unless the account is suspended. In JWT
+/// mode `session_id` becomes the token's `jti`, which is what gets denylisted
+/// on logout. `impersonator_id` is set when an admin acts as `user`.
+pub(super) async fn issue_session(
+    backend: &SessionBackend,
+    cache: &mut Connection<CacheConn>,
+    user: &User,
+    role_codes: &[RoleCode],
+    session_id: String,
+    impersonator_id: Option<i32>,
+) -> Result<Value, Custom<Value>> {
+    if user.disabled_at.is_some() {
+        return Err(Custom(Status::Forbidden, json!("Account suspended")));
+    }
+
+    match backend {
+        SessionBackend::Redis => {
+            // Stored as "<user_id>" or "<user_id>:<impersonator_id>"
+            let session = match impersonator_id {
+                Some(impersonator_id) => format!("{}:{}", user.id, impersonator_id),
+                None => user.id.to_string(),
+            };
+            cache
+                .set_ex::<_, _, ()>(format!("sessions/{}", session_id), session, 3 * 60 * 60)
+                .await
+                .map_err(|e| server_error(&e.into()))?;
+            // Logging the impersonating admin out everywhere ends it too.
+            for owner_id in std::iter::once(user.id).chain(impersonator_id) {
+                let user_sessions = format!("user_sessions/{}", owner_id);
+                cache
+                    .sadd::<_, _, ()>(&user_sessions, &session_id)
+                    .await
+                    .map_err(|e| server_error(&e.into()))?;
+                cache
+                    .expire::<_, ()>(&user_sessions, 3 * 60 * 60)
+                    .await
+                    .map_err(|e| server_error(&e.into()))?;
+            }
+            Ok(json!({ "token": session_id }))
+        }
+        SessionBackend::Jwt(keys) => keys
+            .issue(user.id, role_codes, session_id, impersonator_id)
+            .map(|token| json!({ "token": token }))
+            .map_err(|e| server_error(&(e as Box<dyn std::error::Error>))),
+    }
+}
+
+/// Ends the session `principal` was resolved from.
+pub(super) async fn revoke_session(
+    backend: &SessionBackend,
+    cache: &mut Connection<CacheConn>,
+    principal: &Principal,
+) -> Result<(), Custom<Value>> {
+    let result = match backend {
+        SessionBackend::Redis => {
+            cache
+                .del::<_, ()>(format!("sessions/{}", principal.session_id))
+                .await
+        }
+        SessionBackend::Jwt(_) => {
+            // Only needs to outlive the token itself.
+            let now = jsonwebtoken::get_current_timestamp();
+            let ttl = principal.expires_at.unwrap_or(now).saturating_sub(now).max(1);
+            cache
+                .set_ex::<_, _, ()>(format!("revoked/{}", principal.session_id), 1, ttl as usize)
+                .await
+        }
+    };
+
+    result.map_err(|e| server_error(&e.into()))
+}
+
+/// Logs `user_id` out everywhere. Redis sessions are deleted outright; JWTs
+/// issued up to now are denylisted until the longest of them has expired.
+pub(super) async fn revoke_user_sessions(
+    backend: &SessionBackend,
+    cache: &mut Connection<CacheConn>,
+    user_id: i32,
+) -> Result<(), Custom<Value>> {
+    match backend {
+        SessionBackend::Redis => {
+            let user_sessions = format!("user_sessions/{}", user_id);
+            let session_ids = cache
+                .smembers::<_, Vec<String>>(&user_sessions)
+                .await
+                .map_err(|e| server_error(&e.into()))?;
+            let mut keys: Vec<String> = session_ids
+                .iter()
+                .map(|id| format!("sessions/{}", id))
+                .collect();
+            keys.push(user_sessions);
+            cache
+                .del::<_, ()>(keys)
+                .await
+                .map_err(|e| server_error(&e.into()))
+        }
+        SessionBackend::Jwt(keys) => cache
+            .set_ex::<_, _, ()>(
+                format!("revoked_users/{}", user_id),
+                jsonwebtoken::get_current_timestamp(),
+                keys.ttl() as usize,
+            )
+            .await
+            .map_err(|e| server_error(&e.into())),
+    }
+}
+
+#[post("/logout")]
+pub async fn logout(
+    principal: Principal,
+    backend: &State<SessionBackend>,
+    mut cache: Connection<CacheConn>,
+) -> Result<NoContent, Custom<Value>> {
+    revoke_session(backend, &mut cache, &principal)
         .await
-        .map(|_| json!({ "token": session_id }))
-        .map_err(|e| server_error(&e.into()))
+        .map(|_| NoContent)
 }
 
 #[rocket::get("/me")]
-pub fn me(user: User) -> Value {
-    json!(user)
+pub fn me(user: User, principal: Principal) -> Value {
+    let mut value = json!(user);
+    value["impersonated_by"] = json!(principal.impersonator_id);
+    value
 }
@@ -0,0 +1,149 @@
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::{serde_json::json, Value};
+use validator::ValidationErrors;
+
+use super::{field_messages, v1, validation_error};
+
+/// Items per bulk request, to keep one transaction within reason.
+pub const MAX_ITEMS: usize = 1000;
+
+/// `?mode=` of the bulk routes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The default: one invalid item and nothing is written.
+    AllOrNothing,
+    /// The valid items are written, the invalid ones reported.
+    BestEffort,
+}
+
+impl Mode {
+    pub fn parse(mode: Option<&str>) -> Result<Self, Custom<Value>> {
+        match mode {
+            None | Some("all_or_nothing") => Ok(Mode::AllOrNothing),
+            Some("best_effort") => Ok(Mode::BestEffort),
+            Some(_) => Err(validation_error(v1::field_error(
+                "mode",
+                "mode",
+                "must be all_or_nothing or best_effort",
+            ))),
+        }
+    }
+}
+
+pub fn check_size(field: &'static str, items: usize) -> Result<(), Custom<Value>> {
+    match items {
+        1..=MAX_ITEMS => Ok(()),
+        _ => Err(validation_error(v1::field_error(
+            field,
+            "length",
+            format!("must be 1 to {} items", MAX_ITEMS),
+        ))),
+    }
+}
+
+/// Why an item was not written.
+pub enum ItemError {
+    Invalid(ValidationErrors),
+    /// An update of an id that does not exist.
+    NotFound,
+}
+
+impl From<ValidationErrors> for ItemError {
+    fn from(errors: ValidationErrors) -> Self {
+        ItemError::Invalid(errors)
+    }
+}
+
+impl ItemError {
+    fn result(&self, index: usize) -> Value {
+        match self {
+            ItemError::Invalid(errors) => json!({
+                "index": index,
+                "status": 422,
+                "errors": field_messages(errors),
+            }),
+            ItemError::NotFound => json!({
+                "index": index,
+                "status": 404,
+                "errors": { "id": ["does not exist"] },
+            }),
+        }
+    }
+}
+
+/// The validation errors of an item, `None` for a valid one.
+pub fn item_error(errors: ValidationErrors) -> Option<ItemError> {
+    match errors.is_empty() {
+        true => None,
+        false => Some(errors.into()),
+    }
+}
+
+/// The errors of each item, in order, `None` for a valid one. In
+/// all-or-nothing mode, any error is the answer: `422`, with a `424 Failed
+/// Dependency` for each valid item that was held back.
+pub fn check_items(mode: Mode, errors: &[Option<ItemError>]) -> Result<(), Custom<Value>> {
+    if mode == Mode::BestEffort || errors.iter().all(Option::is_none) {
+        return Ok(());
+    }
+    let results: Vec<Value> = errors
+        .iter()
+        .enumerate()
+        .map(|(index, error)| match error {
+            Some(error) => error.result(index),
+            None => json!({ "index": index, "status": 424 }),
+        })
+        .collect();
+    Err(Custom(
+        Status::UnprocessableEntity,
+        json!({ "results": results }),
+    ))
+}
+
+/// A result per item once the valid ones are `written`, in order, with
+/// `status`: `201` for creates, `200` for updates. The response has that
+/// status when all were written, `207 Multi-Status` otherwise.
+pub fn report(
+    errors: Vec<Option<ItemError>>,
+    written: Vec<Value>,
+    status: Status,
+) -> Custom<Value> {
+    let mut written = written.into_iter();
+    let mut all_written = true;
+    let results: Vec<Value> = errors
+        .into_iter()
+        .enumerate()
+        .map(|(index, error)| match error {
+            Some(error) => {
+                all_written = false;
+                error.result(index)
+            }
+            None => json!({ "index": index, "status": status.code, "data": written.next() }),
+        })
+        .collect();
+    let status = match all_written {
+        true => status,
+        false => Status::MultiStatus,
+    };
+    Custom(status, json!({ "results": results }))
+}
+
+/// `?ids=1,2,3`, deduplicated.
+pub fn parse_ids(ids: &str) -> Result<Vec<i32>, Custom<Value>> {
+    let mut ids = ids
+        .split(',')
+        .map(|id| id.trim().parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| {
+            validation_error(v1::field_error(
+                "ids",
+                "ids",
+                "must be comma-separated ids",
+            ))
+        })?;
+    ids.sort_unstable();
+    ids.dedup();
+    check_size("ids", ids.len())?;
+    Ok(ids)
+}
@@ -0,0 +1,163 @@
+use std::error::Error;
+
+use diesel::PgConnection;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::futures::stream::{self, BoxStream, StreamExt};
+use rocket::http::{ContentType, MediaType, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::status::Custom;
+use rocket::response::stream::ByteStream;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{serde_json::json, Value};
+use rocket::Request;
+
+use crate::catalog::{CatalogError, Format, Page, Report};
+
+use super::{server_error, v1, validation_error, DbConn};
+
+/// The body of an import, unless `limits.import` says otherwise.
+const IMPORT_LIMIT_MIB: u64 = 8;
+
+fn format_of(media_type: &MediaType) -> Option<Format> {
+    if media_type.top() == "text" && media_type.sub() == "csv" {
+        Some(Format::Csv)
+    } else if media_type.top() == "application" && media_type.sub() == "x-ndjson" {
+        Some(Format::Ndjson)
+    } else {
+        None
+    }
+}
+
+/// The export format the client prefers in `Accept`, if any: lists answer
+/// JSON otherwise.
+pub struct Export(Option<Format>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Export {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Export(
+            request
+                .accept()
+                .and_then(|accept| format_of(accept.preferred().media_type())),
+        ))
+    }
+}
+
+impl Export {
+    /// Exports have the fields of the v1 representation, so `?include=` and
+    /// `?fields=` only apply to JSON.
+    pub fn format(
+        self,
+        include: &Option<String>,
+        fields: &Option<String>,
+    ) -> Result<Option<Format>, Custom<Value>> {
+        let format = match self.0 {
+            Some(format) => format,
+            None => return Ok(None),
+        };
+        let field = match (include, fields) {
+            (Some(_), _) => "include",
+            (None, Some(_)) => "fields",
+            (None, None) => return Ok(Some(format)),
+        };
+        Err(validation_error(v1::field_error(
+            field,
+            "format",
+            format!("is not supported with {}", format.media_type()),
+        )))
+    }
+}
+
+pub type Chunks = BoxStream<'static, Vec<u8>>;
+
+/// A list in JSON, or exported chunk by chunk.
+pub enum Listing {
+    Json(Value),
+    Export(Format, Chunks),
+}
+
+impl<'r> Responder<'r, 'r> for Listing {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        let (format, chunks) = match self {
+            Listing::Json(value) => return value.respond_to(request),
+            Listing::Export(format, chunks) => (format, chunks),
+        };
+        let content_type = match format {
+            Format::Csv => ContentType::CSV,
+            Format::Ndjson => ContentType::new("application", "x-ndjson"),
+        };
+        Response::build_from(ByteStream(chunks).respond_to(request)?)
+            .header(content_type)
+            .ok()
+    }
+}
+
+/// Streams the pages of `page` one query at a time, so the table is never
+/// loaded at once. The `200` is sent with the first page: an error after
+/// that is logged and cuts the export short.
+pub fn export(
+    db: DbConn,
+    format: Format,
+    page: fn(&PgConnection, Format, i32) -> Result<Page, CatalogError>,
+) -> Listing {
+    let chunks = stream::unfold((db, 0), move |(db, after)| async move {
+        match db.run(move |c| page(c, format, after)).await {
+            Ok(Some((chunk, last))) => Some((chunk, (db, last))),
+            Ok(None) => None,
+            Err(e) => {
+                log::error!("Export stopped after id {}: {}", after, e);
+                None
+            }
+        }
+    })
+    .boxed();
+    Listing::Export(format, chunks)
+}
+
+/// The format and text of an import body.
+pub async fn read_import(
+    content_type: Option<&ContentType>,
+    limits: &Limits,
+    data: Data<'_>,
+) -> Result<(Format, String), Custom<Value>> {
+    let format = content_type
+        .and_then(|content_type| format_of(content_type.media_type()))
+        .ok_or_else(|| {
+            Custom(
+                Status::UnsupportedMediaType,
+                json!({"error": "Expected text/csv or application/x-ndjson"}),
+            )
+        })?;
+    let limit = limits.get("import").unwrap_or(IMPORT_LIMIT_MIB.mebibytes());
+    let input = data
+        .open(limit)
+        .into_string()
+        .await
+        .map_err(|e| server_error(&e.into()))?;
+    if !input.is_complete() {
+        return Err(Custom(
+            Status::PayloadTooLarge,
+            json!({"error": format!("Imports are limited to {}", limit)}),
+        ));
+    }
+    Ok((format, input.into_inner()))
+}
+
+/// `200` with the report of a dry run or of an import without errors,
+/// `207 Multi-Status` when the valid lines were written despite errors and
+/// `422` when the errors kept any from being written.
+pub fn report(
+    result: Result<Report, CatalogError>,
+    dry_run: bool,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let report = result.map_err(|e| server_error(&(e as Box<dyn Error>)))?;
+    let status = match (report.has_errors(), report.applied) {
+        (false, _) => Status::Ok,
+        (true, _) if dry_run => Status::Ok,
+        (true, true) => Status::MultiStatus,
+        (true, false) => Status::UnprocessableEntity,
+    };
+    Ok(Custom(status, json!(report)))
+}
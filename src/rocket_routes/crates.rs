@@ -1,29 +1,125 @@
-use rocket::http::Status;
+use std::collections::HashSet;
+
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::{Connection as _, PgConnection, QueryResult};
+use rocket::data::{Data, Limits};
+use rocket::http::{ContentType, Status};
 use rocket::response::status::{Custom, NoContent};
 use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket_db_pools::Connection;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 use crate::diesel::result::Error::NotFound;
-use crate::models::{Crate, NewCrate};
-use crate::repositories::CrateRepository;
+use crate::models;
+use crate::repositories::{CrateRepository, RustaceanRepository};
 use crate::rocket_routes::DbConn;
+use crate::webhooks;
+
+use super::bulk;
+use super::catalog::{self, Export, Listing};
+use super::idempotency::{self, Attempt, IdempotencyKey};
+use super::v1::{self, Crate, CrateWithRustacean, NewCrate, Representation, UpdateCrate};
+use super::{
+    body_error, etag, server_error, validation_error, weak_etag, Body, CacheConn, EditorUser,
+    IfMatch, Tagged, TransactionError,
+};
+
+pub(crate) const UNKNOWN_RUSTACEAN: &str = "is not a known rustacean";
+
+/// An unknown `rustacean_id` is the caller's mistake, not ours.
+fn write_error(e: TransactionError) -> Custom<Value> {
+    match e {
+        TransactionError::Respond(response) => response,
+        TransactionError::Database(NotFound) => {
+            Custom(Status::NotFound, json!({"error": "Crate not found"}))
+        }
+        TransactionError::Database(DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            validation_error(v1::field_error("rustacean_id", "exists", UNKNOWN_RUSTACEAN))
+        }
+        TransactionError::Database(e) => server_error(&e.into()),
+    }
+}
 
-use super::{server_error, EditorUser};
+fn tagged(a_crate: models::Crate) -> Tagged {
+    Tagged {
+        etag: etag(a_crate.updated_at),
+        value: json!(Crate::from(a_crate)),
+    }
+}
 
-#[get("/crates")]
-pub async fn get_crates(db: DbConn, _user: EditorUser) -> Result<Value, Custom<Value>> {
-    db.run(|c| {
-        CrateRepository::find_multiple(c, 100)
-            .map(|crates| json!(crates))
+/// `?include=rustacean` embeds each crate's rustacean, and `?fields=` picks
+/// the fields of the crates, e.g. `?fields=id,name`. With `Accept: text/csv`
+/// or `application/x-ndjson`, every crate is exported instead.
+#[get("/crates?<include>&<fields>")]
+pub async fn get_crates(
+    db: DbConn,
+    _user: EditorUser,
+    export: Export,
+    include: Option<String>,
+    fields: Option<String>,
+) -> Result<Listing, Custom<Value>> {
+    if let Some(format) = export.format(&include, &fields)? {
+        return Ok(catalog::export(db, format, crate::catalog::export_crates));
+    }
+    let representation = Representation::parse(
+        include.as_deref(),
+        "rustacean",
+        fields.as_deref(),
+        &Crate::FIELDS,
+    )
+    .map_err(validation_error)?;
+    db.run(move |c| {
+        let crates = match representation.include {
+            Some(_) => CrateRepository::find_multiple_with_rustacean(c, 100).map(|crates| {
+                json!(crates
+                    .into_iter()
+                    .map(CrateWithRustacean::from)
+                    .collect::<Vec<_>>())
+            }),
+            None => CrateRepository::find_multiple(c, 100)
+                .map(|crates| json!(crates.into_iter().map(Crate::from).collect::<Vec<_>>())),
+        };
+        crates
+            .map(|crates| Listing::Json(representation.select(crates)))
             .map_err(|e| server_error(&e.into()))
     })
     .await
 }
 
-#[get("/crates/<id>")]
-pub async fn view_crate(db: DbConn, _user: EditorUser, id: i32) -> Result<Value, Custom<Value>> {
+/// Takes `?include=` and `?fields=` like `GET /crates`.
+#[get("/crates/<id>?<include>&<fields>")]
+pub async fn view_crate(
+    db: DbConn,
+    _user: EditorUser,
+    id: i32,
+    include: Option<String>,
+    fields: Option<String>,
+) -> Result<Tagged, Custom<Value>> {
+    let representation = Representation::parse(
+        include.as_deref(),
+        "rustacean",
+        fields.as_deref(),
+        &Crate::FIELDS,
+    )
+    .map_err(validation_error)?;
     db.run(move |c| {
-        CrateRepository::find(c, id)
-            .map(|view_crate: Crate| json!(view_crate))
+        let view_crate = match representation.include {
+            Some(_) => CrateRepository::find_with_rustacean(c, id).map(|(a_crate, rustacean)| {
+                Tagged {
+                    etag: weak_etag(&[
+                        (a_crate.id, a_crate.updated_at),
+                        (rustacean.id, rustacean.updated_at),
+                    ]),
+                    value: json!(CrateWithRustacean::from((a_crate, rustacean))),
+                }
+            }),
+            None => CrateRepository::find(c, id).map(tagged),
+        };
+        view_crate
+            .map(|view_crate| Tagged {
+                etag: view_crate.etag,
+                value: representation.select(view_crate.value),
+            })
             .map_err(|e| match e {
                 NotFound => Custom(Status::NotFound, json!({"error": "Crate not found"})),
                 _ => server_error(&e.into()),
@@ -32,45 +128,291 @@ pub async fn view_crate(db: DbConn, _user: EditorUser, id: i32) -> Result<Value,
     .await
 }
 
+/// A retry with the same `Idempotency-Key` gets the first response again.
 #[post("/crates", format = "json", data = "<new_crate>")]
 pub async fn create_crate(
+    db: DbConn,
+    mut cache: Connection<CacheConn>,
+    user: EditorUser,
+    idempotency_key: IdempotencyKey,
+    new_crate: Body<NewCrate>,
+) -> Result<Custom<Tagged>, Custom<Value>> {
+    new_crate.validate().map_err(validation_error)?;
+    let attempt = match idempotency::begin(
+        &mut cache,
+        &user.0,
+        idempotency_key,
+        "POST /crates",
+        &*new_crate,
+    )
+    .await?
+    {
+        Attempt::Replay(response) => return Ok(response),
+        attempt => attempt,
+    };
+
+    let result = db
+        .run(move |c| {
+            c.transaction(|| -> Result<Tagged, TransactionError> {
+                let new_crate = tagged(CrateRepository::create(c, new_crate.into_inner().into())?);
+                webhooks::enqueue(c, webhooks::CRATE_CREATED, new_crate.value.clone())?;
+                Ok(new_crate)
+            })
+            .map(|new_crate| Custom(Status::Created, new_crate))
+            .map_err(write_error)
+        })
+        .await;
+    idempotency::finish(&mut cache, attempt, &result).await;
+    result
+}
+
+/// Creates up to `bulk::MAX_ITEMS` crates with a single insert, in one
+/// transaction, answering with a result per crate. With
+/// `?mode=all_or_nothing`, the default, one invalid crate means none is
+/// created; with `?mode=best_effort` the valid ones still are.
+#[post("/crates/bulk?<mode>", format = "json", data = "<new_crates>")]
+pub async fn create_crates(
     db: DbConn,
     _user: EditorUser,
-    new_crate: Json<NewCrate>,
+    mode: Option<String>,
+    new_crates: Json<Vec<NewCrate>>,
 ) -> Result<Custom<Value>, Custom<Value>> {
+    let mode = bulk::Mode::parse(mode.as_deref())?;
+    let new_crates = new_crates.into_inner();
+    bulk::check_size("items", new_crates.len())?;
     db.run(move |c| {
-        CrateRepository::create(c, new_crate.into_inner())
-            .map(|new_crate| Custom(Status::Created, json!(new_crate)))
-            .map_err(|e| server_error(&e.into()))
+        c.transaction(|| {
+            let known = known_rustaceans(c, new_crates.iter())?;
+            let errors: Vec<Option<bulk::ItemError>> = new_crates
+                .iter()
+                .map(|new_crate| bulk::item_error(crate_errors(new_crate, &known)))
+                .collect();
+            bulk::check_items(mode, &errors).map_err(TransactionError::Respond)?;
+
+            let valid: Vec<models::NewCrate> = new_crates
+                .into_iter()
+                .zip(&errors)
+                .filter(|(_, error)| error.is_none())
+                .map(|(new_crate, _)| new_crate.into())
+                .collect();
+            let created: Vec<Value> = CrateRepository::create_multiple(c, valid)?
+                .into_iter()
+                .map(|a_crate| json!(Crate::from(a_crate)))
+                .collect();
+            for a_crate in &created {
+                webhooks::enqueue(c, webhooks::CRATE_CREATED, a_crate.clone())?;
+            }
+            Ok(bulk::report(errors, created, Status::Created))
+        })
+        .map_err(write_error)
+    })
+    .await
+}
+
+/// Replaces up to `bulk::MAX_ITEMS` crates, each given with its `id`, in one
+/// transaction, answering with a result per crate like `POST /crates/bulk`.
+/// An unknown `id` is a `404` for that crate.
+#[put("/crates/bulk?<mode>", format = "json", data = "<updates>")]
+pub async fn update_crates(
+    db: DbConn,
+    _user: EditorUser,
+    mode: Option<String>,
+    updates: Json<Vec<UpdateCrate>>,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let mode = bulk::Mode::parse(mode.as_deref())?;
+    let updates = updates.into_inner();
+    bulk::check_size("items", updates.len())?;
+    db.run(move |c| {
+        c.transaction(|| {
+            let ids = updates.iter().filter_map(|update| update.id).collect();
+            let existing: HashSet<i32> =
+                CrateRepository::find_ids_for_update(c, ids)?.into_iter().collect();
+            let known = known_rustaceans(c, updates.iter().map(|update| &update.a_crate))?;
+            let errors: Vec<Option<bulk::ItemError>> = updates
+                .iter()
+                .map(|update| {
+                    let mut errors = crate_errors(&update.a_crate, &known);
+                    match update.id {
+                        None => errors.add("id", v1::required()),
+                        Some(id) if errors.is_empty() && !existing.contains(&id) => {
+                            return Some(bulk::ItemError::NotFound)
+                        }
+                        Some(_) => {}
+                    }
+                    bulk::item_error(errors)
+                })
+                .collect();
+            bulk::check_items(mode, &errors).map_err(TransactionError::Respond)?;
+
+            let mut updated = vec![];
+            for (update, _) in updates.into_iter().zip(&errors).filter(|(_, e)| e.is_none()) {
+                if let Some(id) = update.id {
+                    let a_crate = json!(Crate::from(CrateRepository::save(c, id, update.into())?));
+                    webhooks::enqueue(c, webhooks::CRATE_UPDATED, a_crate.clone())?;
+                    updated.push(a_crate);
+                }
+            }
+            Ok(bulk::report(errors, updated, Status::Ok))
+        })
+        .map_err(write_error)
     })
     .await
 }
 
+/// The rustaceans `crates` belong to that exist, locked until the end of the
+/// transaction so that none is deleted before the crates are written.
+fn known_rustaceans<'a>(
+    c: &PgConnection,
+    crates: impl Iterator<Item = &'a NewCrate>,
+) -> QueryResult<HashSet<i32>> {
+    let rustacean_ids = crates.map(|a_crate| a_crate.rustacean_id).collect();
+    Ok(RustaceanRepository::find_ids_for_share(c, rustacean_ids)?
+        .into_iter()
+        .collect())
+}
+
+/// `validate()`, and whether the crate's rustacean is one of the `known`.
+fn crate_errors(a_crate: &NewCrate, known: &HashSet<i32>) -> ValidationErrors {
+    let mut errors = a_crate.validate().err().unwrap_or_default();
+    if !known.contains(&a_crate.rustacean_id) {
+        let mut error = ValidationError::new("exists");
+        error.message = Some(UNKNOWN_RUSTACEAN.into());
+        errors.add("rustacean_id", error);
+    }
+    errors
+}
+
+/// Creates or updates crates by `code` from a `text/csv` or
+/// `application/x-ndjson` body, with the fields of `NewCrate`, answering with
+/// a report per line. `?dry_run=true` only reports what would change;
+/// `?mode=` is as for `POST /crates/bulk`.
+#[post("/crates/import?<dry_run>&<mode>", data = "<data>")]
+pub async fn import_crates(
+    db: DbConn,
+    _user: EditorUser,
+    content_type: Option<&ContentType>,
+    limits: &Limits,
+    dry_run: Option<bool>,
+    mode: Option<String>,
+    data: Data<'_>,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let all_or_nothing = bulk::Mode::parse(mode.as_deref())? == bulk::Mode::AllOrNothing;
+    let dry_run = dry_run.unwrap_or(false);
+    let (format, input) = catalog::read_import(content_type, limits, data).await?;
+    let result = db
+        .run(move |c| {
+            crate::catalog::import_crates(c, format, &input, dry_run, all_or_nothing)
+        })
+        .await;
+    catalog::report(result, dry_run)
+}
+
+/// Honours `If-Match`, answering `412` if the crate changed in the meantime.
 #[put("/crates/<id>", format = "json", data = "<update_crate>")]
 pub async fn update_crate(
     db: DbConn,
     _user: EditorUser,
+    if_match: IfMatch,
     id: i32,
-    update_crate: Json<Crate>,
-) -> Result<Value, Custom<Value>> {
+    update_crate: Body<UpdateCrate>,
+) -> Result<Tagged, Custom<Value>> {
+    v1::validate_update(&*update_crate, update_crate.id, id).map_err(validation_error)?;
     db.run(move |c| {
-        CrateRepository::save(c, id, update_crate.into_inner())
-            .map(|updated_crate| json!(updated_crate))
-            .map_err(|e| server_error(&e.into()))
+        c.transaction(|| {
+            let current = CrateRepository::find_for_update(c, id)?;
+            if_match.check(&etag(current.updated_at))?;
+            let updated_crate =
+                tagged(CrateRepository::save(c, id, update_crate.into_inner().into())?);
+            webhooks::enqueue(c, webhooks::CRATE_UPDATED, updated_crate.value.clone())?;
+            Ok(updated_crate)
+        })
+        .map_err(write_error)
+    })
+    .await
+}
+
+/// Only the fields in the `application/merge-patch+json` body change.
+/// Honours `If-Match` like `PUT`.
+#[patch("/crates/<id>", format = "application/merge-patch+json", data = "<patch>")]
+pub async fn patch_crate(
+    db: DbConn,
+    _user: EditorUser,
+    if_match: IfMatch,
+    id: i32,
+    patch: Json<Value>,
+) -> Result<Tagged, Custom<Value>> {
+    let patch = patch.into_inner();
+    db.run(move |c| {
+        c.transaction(|| {
+            let current = CrateRepository::find_for_update(c, id)?;
+            if_match.check(&etag(current.updated_at))?;
+            let update: UpdateCrate = v1::patched(Crate::from(current), &patch)
+                .map_err(|errors| TransactionError::Respond(body_error(errors)))?;
+            v1::validate_update(&update, update.id, id)
+                .map_err(|errors| TransactionError::Respond(validation_error(errors)))?;
+            let patched_crate = tagged(CrateRepository::save(c, id, update.into())?);
+            webhooks::enqueue(c, webhooks::CRATE_UPDATED, patched_crate.value.clone())?;
+            Ok(patched_crate)
+        })
+        .map_err(write_error)
     })
     .await
 }
 
+/// Honours `If-Match` like `PUT`. Deleting a crate again answers `404`, which
+/// leaves things as they were, so a retried `DELETE` is safe.
 #[delete("/crates/<id>")]
 pub async fn delete_crate(
     db: DbConn,
     _user: EditorUser,
+    if_match: IfMatch,
     id: i32,
 ) -> Result<NoContent, Custom<Value>> {
     db.run(move |c| {
-        CrateRepository::delete(c, id)
-            .map(|_| NoContent)
-            .map_err(|e| server_error(&e.into()))
+        c.transaction(|| {
+            let current = CrateRepository::find_for_update(c, id)?;
+            if_match.check(&etag(current.updated_at))?;
+            CrateRepository::delete(c, id)?;
+            webhooks::enqueue(c, webhooks::CRATE_DELETED, json!({ "id": id }))?;
+            Ok(())
+        })
+        .map(|_| NoContent)
+        .map_err(write_error)
+    })
+    .await
+}
+
+/// Deletes the crates in `?ids=1,2,3` with a single statement. With
+/// `?mode=all_or_nothing`, the default, nothing is deleted if one of them does
+/// not exist; with `?mode=best_effort` the others still are.
+#[delete("/crates?<ids>&<mode>")]
+pub async fn delete_crates(
+    db: DbConn,
+    _user: EditorUser,
+    ids: String,
+    mode: Option<String>,
+) -> Result<Value, Custom<Value>> {
+    let mode = bulk::Mode::parse(mode.as_deref())?;
+    let ids = bulk::parse_ids(&ids)?;
+    db.run(move |c| {
+        c.transaction(|| {
+            let deleted = CrateRepository::delete_multiple(c, ids.clone())?;
+            let not_found: Vec<i32> = ids
+                .into_iter()
+                .filter(|id| !deleted.contains(id))
+                .collect();
+            if mode == bulk::Mode::AllOrNothing && !not_found.is_empty() {
+                return Err(TransactionError::Respond(Custom(
+                    Status::NotFound,
+                    json!({ "deleted": [], "not_found": not_found }),
+                )));
+            }
+            for id in &deleted {
+                webhooks::enqueue(c, webhooks::CRATE_DELETED, json!({ "id": id }))?;
+            }
+            Ok(json!({ "deleted": deleted, "not_found": not_found }))
+        })
+        .map_err(write_error)
     })
     .await
 }
@@ -0,0 +1,50 @@
+use rocket::http::Status;
+use rocket::response::content::RawHtml;
+use rocket::response::status::Custom;
+use rocket::serde::json::{serde_json::json, Value};
+
+use crate::commands;
+use crate::repositories::CrateRepository;
+
+use super::{server_error, DbConn, EditorUser};
+
+fn parse_point_in_time(
+    value: Option<String>,
+) -> Result<Option<chrono::NaiveDateTime>, Custom<Value>> {
+    value
+        .map(|v| commands::parse_point_in_time(&v))
+        .transpose()
+        .map_err(|e| Custom(Status::UnprocessableEntity, json!(e)))
+}
+
+/// The digest HTML as `digest-send` would render it right now. `since` and
+/// `until` take the same values as the CLI; without `since` the window
+/// continues from the last digest sent to `to`, or covers the past day, and
+/// it is in `locale` or else the locale of the user with that address.
+/// Templates are loaded on every request so edits show up straight away.
+#[get("/digest/preview?<since>&<until>&<to>&<locale>")]
+pub async fn preview(
+    db: DbConn,
+    _user: EditorUser,
+    since: Option<String>,
+    until: Option<String>,
+    to: Option<String>,
+    locale: Option<String>,
+) -> Result<RawHtml<String>, Custom<Value>> {
+    let since = parse_point_in_time(since)?;
+    let until = parse_point_in_time(until)?;
+    let to = to.unwrap_or_default();
+
+    db.run(move |c| -> Result<RawHtml<String>, Custom<Value>> {
+        let (since, until) = commands::digest_window(c, &to, since, until)
+            .map_err(|e| server_error(&e.into()))?;
+        let locale = commands::recipient_locale(c, &to, locale)
+            .map_err(|e| server_error(&e.into()))?;
+        let crates = CrateRepository::find_between(c, since, until)
+            .map_err(|e| server_error(&e.into()))?;
+        commands::render_digest(&to, locale.as_deref(), &crates, since, until)
+            .map(|email| RawHtml(email.html_body))
+            .map_err(|e| server_error(&e))
+    })
+    .await
+}
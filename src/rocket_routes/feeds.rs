@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Status};
+use rocket::response::status::Custom;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{serde_json::json, Value};
+use rocket::{Request, State};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tera::{Context, Tera};
+
+use crate::diesel::result::Error::NotFound;
+use crate::models::{Crate, Rustacean};
+use crate::repositories::{CrateRepository, RustaceanRepository};
+
+use super::{server_error, v1, DbConn};
+
+/// From `ROCKET_FEEDS`, all optional.
+#[derive(Deserialize)]
+pub struct FeedsConfig {
+    /// When set, feeds are only served with `?token=<token>`, since feed
+    /// readers rarely let you add an `Authorization` header.
+    pub token: Option<String>,
+    /// How far back the feeds go.
+    #[serde(default = "default_window_hours")]
+    pub window_hours: i32,
+    /// Base of the links in the feeds.
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+}
+
+fn default_window_hours() -> i32 {
+    7 * 24
+}
+
+fn default_base_url() -> String {
+    "http://127.0.0.1:8000".to_owned()
+}
+
+impl Default for FeedsConfig {
+    fn default() -> Self {
+        FeedsConfig {
+            token: None,
+            window_hours: default_window_hours(),
+            base_url: default_base_url(),
+        }
+    }
+}
+
+/// The feed templates, parsed once at ignite.
+pub struct FeedTemplates(Tera);
+
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Feeds", |rocket| async {
+        let config = match rocket.figment().find_value("feeds") {
+            Ok(_) => rocket.figment().extract_inner::<FeedsConfig>("feeds"),
+            Err(_) => Ok(FeedsConfig::default()),
+        };
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Invalid feeds configuration: {}", e);
+                return Err(rocket);
+            }
+        };
+        match Tera::new("templates/feeds/*.xml") {
+            Ok(tera) => Ok(rocket.manage(config).manage(FeedTemplates(tera))),
+            Err(e) => {
+                log::error!("Invalid feed templates: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// A rendered feed. Answers `304 Not Modified` when the request's
+/// `If-None-Match` matches its ETag or, without one, when nothing changed
+/// since `If-Modified-Since`. An empty feed has no `Last-Modified`.
+pub struct Feed {
+    body: String,
+    content_type: ContentType,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl Feed {
+    fn etag(&self) -> String {
+        let digest = Sha256::digest(self.body.as_bytes());
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("\"{}\"", &hex[..32])
+    }
+
+    fn is_fresh(&self, request: &Request<'_>, etag: &str) -> bool {
+        if let Some(tags) = request.headers().get_one("If-None-Match") {
+            return tags.split(',').any(|tag| {
+                let tag = tag.trim();
+                tag == "*" || tag.trim_start_matches("W/") == etag
+            });
+        }
+        let since = request
+            .headers()
+            .get_one("If-Modified-Since")
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok());
+        match (self.last_modified, since) {
+            (Some(last_modified), Some(since)) => last_modified.timestamp() <= since.timestamp(),
+            _ => false,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Feed {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let etag = self.etag();
+        let mut response = Response::build();
+        response
+            .raw_header("ETag", etag.to_owned())
+            .raw_header("Cache-Control", "no-cache");
+        if let Some(last_modified) = self.last_modified {
+            response.raw_header(
+                "Last-Modified",
+                last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            );
+        }
+        if self.is_fresh(request, &etag) {
+            return response.status(Status::NotModified).ok();
+        }
+        response
+            .header(self.content_type)
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .ok()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+fn check_token(config: &FeedsConfig, token: Option<String>) -> Result<(), Custom<Value>> {
+    match &config.token {
+        Some(expected) if token.as_ref() != Some(expected) => {
+            Err(Custom(Status::Unauthorized, json!("Invalid feed token")))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The newest crate is when the feed last changed. An empty feed still needs
+/// an updated date, `fallback`.
+#[allow(clippy::too_many_arguments)]
+fn render_feed(
+    templates: &FeedTemplates,
+    format: FeedFormat,
+    base_url: &str,
+    path: &str,
+    title: &str,
+    crates: &[Crate],
+    authors: &HashMap<i32, String>,
+    fallback: NaiveDateTime,
+) -> Result<Feed, Custom<Value>> {
+    let last_modified = crates
+        .iter()
+        .map(|a_crate| a_crate.created_at)
+        .max()
+        .map(|newest| Utc.from_utc_datetime(&newest));
+    let updated = last_modified.unwrap_or_else(|| Utc.from_utc_datetime(&fallback));
+    let entries: Vec<Value> = crates
+        .iter()
+        .map(|a_crate| {
+            let created_at = Utc.from_utc_datetime(&a_crate.created_at);
+            json!({
+                "crate": a_crate,
+                "url": format!("{}{}/crates/{}", base_url, v1::PREFIX, a_crate.id),
+                "author": authors.get(&a_crate.rustacean_id).cloned().unwrap_or_default(),
+                "updated": created_at.to_rfc3339(),
+                "pub_date": created_at.to_rfc2822(),
+            })
+        })
+        .collect();
+
+    let mut context = Context::new();
+    context.insert("title", title);
+    context.insert("site_url", base_url);
+    context.insert("self_url", &format!("{}{}", base_url, path));
+    context.insert("updated", &updated.to_rfc3339());
+    context.insert("last_build_date", &updated.to_rfc2822());
+    context.insert("entries", &entries);
+
+    let (template, content_type) = match format {
+        FeedFormat::Atom => (
+            "atom.xml",
+            ContentType::new("application", "atom+xml").with_params(("charset", "utf-8")),
+        ),
+        FeedFormat::Rss => (
+            "rss.xml",
+            ContentType::new("application", "rss+xml").with_params(("charset", "utf-8")),
+        ),
+    };
+    let body = templates
+        .0
+        .render(template, &context)
+        .map_err(|e| server_error(&e.into()))?;
+    Ok(Feed {
+        body,
+        content_type,
+        last_modified,
+    })
+}
+
+async fn crates_feed(
+    db: DbConn,
+    config: &FeedsConfig,
+    templates: &FeedTemplates,
+    format: FeedFormat,
+    path: &'static str,
+) -> Result<Feed, Custom<Value>> {
+    let window_hours = config.window_hours;
+    let (crates, authors) = db
+        .run(move |c| -> Result<_, Custom<Value>> {
+            let crates = CrateRepository::find_since(c, window_hours)
+                .map_err(|e| server_error(&e.into()))?;
+            let rustacean_ids = crates.iter().map(|a_crate| a_crate.rustacean_id).collect();
+            let authors = RustaceanRepository::find_by_ids(c, rustacean_ids)
+                .map_err(|e| server_error(&e.into()))?
+                .into_iter()
+                .map(|rustacean| (rustacean.id, rustacean.name))
+                .collect();
+            Ok((crates, authors))
+        })
+        .await?;
+    render_feed(
+        templates,
+        format,
+        config.base_url.trim_end_matches('/'),
+        path,
+        "New crates on Cr8s",
+        &crates,
+        &authors,
+        NaiveDateTime::from_timestamp_opt(0, 0).unwrap(),
+    )
+}
+
+#[get("/feeds/crates.atom?<token>")]
+pub async fn crates_atom(
+    db: DbConn,
+    config: &State<FeedsConfig>,
+    templates: &State<FeedTemplates>,
+    token: Option<String>,
+) -> Result<Feed, Custom<Value>> {
+    check_token(config, token)?;
+    crates_feed(db, config, templates, FeedFormat::Atom, "/feeds/crates.atom").await
+}
+
+#[get("/feeds/crates.rss?<token>")]
+pub async fn crates_rss(
+    db: DbConn,
+    config: &State<FeedsConfig>,
+    templates: &State<FeedTemplates>,
+    token: Option<String>,
+) -> Result<Feed, Custom<Value>> {
+    check_token(config, token)?;
+    crates_feed(db, config, templates, FeedFormat::Rss, "/feeds/crates.rss").await
+}
+
+/// `<id>.atom`, as Rocket parameters span a whole path segment.
+#[get("/feeds/rustaceans/<file>?<token>")]
+pub async fn rustacean_atom(
+    db: DbConn,
+    config: &State<FeedsConfig>,
+    templates: &State<FeedTemplates>,
+    file: String,
+    token: Option<String>,
+) -> Result<Feed, Custom<Value>> {
+    check_token(config, token)?;
+    let id: i32 = file
+        .strip_suffix(".atom")
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| Custom(Status::NotFound, json!("Feed not found")))?;
+    let window_hours = config.window_hours;
+    let (rustacean, crates) = db
+        .run(move |c| -> Result<(Rustacean, Vec<Crate>), Custom<Value>> {
+            let rustacean = RustaceanRepository::find(c, id).map_err(|e| match e {
+                NotFound => Custom(Status::NotFound, json!("Rustacean not found")),
+                _ => server_error(&e.into()),
+            })?;
+            let crates = CrateRepository::find_since_by_rustacean(c, id, window_hours)
+                .map_err(|e| server_error(&e.into()))?;
+            Ok((rustacean, crates))
+        })
+        .await?;
+    let authors = HashMap::from([(rustacean.id, rustacean.name.to_owned())]);
+    render_feed(
+        templates,
+        FeedFormat::Atom,
+        config.base_url.trim_end_matches('/'),
+        &format!("/feeds/rustaceans/{}.atom", id),
+        &format!("Crates by {}", rustacean.name),
+        &crates,
+        &authors,
+        rustacean.created_at,
+    )
+}
@@ -0,0 +1,161 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::status::Custom;
+use rocket::serde::json::{serde_json::json, Value};
+use rocket::Request;
+use rocket_db_pools::deadpool_redis::redis::{self, AsyncCommands};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{server_error, CacheConn, Principal, Tagged};
+
+/// How long a response is remembered, and so how long a client may retry.
+const TTL_SECONDS: usize = 24 * 60 * 60;
+
+/// How long a key stays reserved for a request that never finishes, say
+/// because the server died, before retries may go ahead.
+const LEASE_SECONDS: usize = 60;
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// The `Idempotency-Key` header of a `POST`, if any.
+pub struct IdempotencyKey(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IdempotencyKey(
+            request
+                .headers()
+                .get_one("Idempotency-Key")
+                .map(str::to_owned),
+        ))
+    }
+}
+
+/// What Redis holds for a key: the request it was first used with and, once
+/// that request succeeded, its response.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    fingerprint: String,
+    status: Option<u16>,
+    etag: Option<String>,
+    body: Option<Value>,
+}
+
+pub enum Attempt {
+    /// No `Idempotency-Key` was sent.
+    Untracked,
+    /// The key is new and reserved for this request until `finish`, or for
+    /// `LEASE_SECONDS` at most.
+    First { key: String, fingerprint: String },
+    /// The key was used before with the same request: answer as then.
+    Replay(Custom<Tagged>),
+}
+
+fn fingerprint(route: &str, body: &impl Serialize) -> String {
+    let digest = Sha256::digest(format!("{} {}", route, json!(body)).as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Reserves `key` for this request, or finds what it was used for before.
+/// Keys are per user, so nobody can replay someone else's response.
+pub async fn begin(
+    cache: &mut Connection<CacheConn>,
+    principal: &Principal,
+    key: IdempotencyKey,
+    route: &str,
+    body: &impl Serialize,
+) -> Result<Attempt, Custom<Value>> {
+    let key = match key.0 {
+        Some(key) if key.is_empty() || key.len() > MAX_KEY_LENGTH => {
+            return Err(Custom(
+                Status::UnprocessableEntity,
+                json!("Invalid Idempotency-Key"),
+            ))
+        }
+        Some(key) => format!("idempotency/{}/{}", principal.user_id, key),
+        None => return Ok(Attempt::Untracked),
+    };
+    let fingerprint = fingerprint(route, body);
+
+    let reservation = Entry {
+        fingerprint: fingerprint.to_owned(),
+        status: None,
+        etag: None,
+        body: None,
+    };
+    let reserved: Option<String> = redis::cmd("SET")
+        .arg(&key)
+        .arg(json!(reservation).to_string())
+        .arg("NX")
+        .arg("EX")
+        .arg(LEASE_SECONDS)
+        .query_async(&mut **cache)
+        .await
+        .map_err(|e| server_error(&e.into()))?;
+    if reserved.is_some() {
+        return Ok(Attempt::First { key, fingerprint });
+    }
+
+    let entry: Option<Entry> = cache
+        .get::<_, Option<String>>(&key)
+        .await
+        .map_err(|e| server_error(&e.into()))?
+        .and_then(|entry| serde_json::from_str(&entry).ok());
+    let entry = match entry {
+        Some(entry) if entry.fingerprint != fingerprint => {
+            return Err(Custom(
+                Status::UnprocessableEntity,
+                json!("Idempotency-Key was already used for a different request"),
+            ))
+        }
+        Some(entry) => entry,
+        // Expired or released since the SET, as good as new.
+        None => return Ok(Attempt::First { key, fingerprint }),
+    };
+    match (entry.status, entry.etag, entry.body) {
+        (Some(status), Some(etag), Some(value)) => Ok(Attempt::Replay(Custom(
+            Status::from_code(status).unwrap_or(Status::Created),
+            Tagged { etag, value },
+        ))),
+        _ => Err(Custom(
+            Status::Conflict,
+            json!("A request with this Idempotency-Key is still in progress"),
+        )),
+    }
+}
+
+/// Stores the response of a successful first attempt, or releases the key
+/// after a failed one so that the client can retry. The outcome of the
+/// request stands either way, so Redis errors are only logged.
+pub async fn finish(
+    cache: &mut Connection<CacheConn>,
+    attempt: Attempt,
+    result: &Result<Custom<Tagged>, Custom<Value>>,
+) {
+    let (key, fingerprint) = match attempt {
+        Attempt::First { key, fingerprint } => (key, fingerprint),
+        _ => return,
+    };
+    let stored = match result {
+        Ok(Custom(status, tagged)) => {
+            let entry = Entry {
+                fingerprint,
+                status: Some(status.code),
+                etag: Some(tagged.etag.to_owned()),
+                body: Some(tagged.value.clone()),
+            };
+            cache
+                .set_ex::<_, _, ()>(&key, json!(entry).to_string(), TTL_SECONDS)
+                .await
+        }
+        Err(_) => cache.del::<_, ()>(&key).await,
+    };
+    if let Err(e) = stored {
+        log::error!("Cannot store the response for {}: {}", key, e);
+    }
+}
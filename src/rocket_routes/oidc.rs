@@ -0,0 +1,189 @@
+use std::error::Error;
+
+use diesel::{Connection as _, PgConnection};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::response::Redirect;
+use rocket::serde::json::{serde_json::json, Value};
+use rocket::State;
+use rocket_db_pools::{deadpool_redis::redis::AsyncCommands, Connection};
+
+use crate::auth;
+use crate::i18n;
+use crate::diesel::result::Error::NotFound;
+use crate::models::{NewUser, RoleCode, User};
+use crate::oidc::{self, AuthorizationRequest, IdTokenClaims, OidcConfig};
+use crate::repositories::UserRepository;
+
+use super::authorization::issue_session;
+use super::{server_error, CacheConn, DbConn, SessionBackend, TransactionError};
+
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("OIDC config", |rocket| async {
+        if rocket.figment().find_value("oidc").is_err() {
+            return Ok(rocket);
+        }
+        match rocket.figment().extract_inner::<OidcConfig>("oidc") {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                log::error!("Invalid OIDC configuration: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+fn not_configured() -> Custom<Value> {
+    Custom(Status::NotFound, json!("OIDC login is not configured"))
+}
+
+fn login_error(error: Box<dyn Error + Send + Sync>) -> Custom<Value> {
+    log::warn!("OIDC login failed: {}", error);
+    Custom(Status::Unauthorized, json!("OIDC login failed"))
+}
+
+#[get("/auth/oidc/login")]
+pub async fn login(
+    config: Option<&State<OidcConfig>>,
+    mut cache: Connection<CacheConn>,
+) -> Result<Redirect, Custom<Value>> {
+    let config = config.ok_or_else(not_configured)?;
+    let metadata = oidc::discover(config)
+        .await
+        .map_err(|e| server_error(&(e as Box<dyn Error>)))?;
+
+    let request = AuthorizationRequest::new();
+    let url = request
+        .authorize_url(config, &metadata)
+        .map_err(|e| server_error(&(e as Box<dyn Error>)))?;
+
+    cache
+        .set_ex::<_, _, ()>(
+            format!("oidc/{}", request.state),
+            json!(request).to_string(),
+            10 * 60,
+        )
+        .await
+        .map_err(|e| server_error(&e.into()))?;
+
+    Ok(Redirect::to(url.to_string()))
+}
+
+#[get("/auth/oidc/callback?<code>&<state>")]
+pub async fn callback(
+    db: DbConn,
+    config: Option<&State<OidcConfig>>,
+    backend: &State<SessionBackend>,
+    mut cache: Connection<CacheConn>,
+    code: String,
+    state: String,
+) -> Result<Value, Custom<Value>> {
+    let config = config.ok_or_else(not_configured)?;
+
+    // The pending request is single use, whatever the outcome of this callback.
+    let key = format!("oidc/{}", state);
+    let pending = cache
+        .get::<_, Option<String>>(&key)
+        .await
+        .map_err(|e| server_error(&e.into()))?;
+    cache
+        .del::<_, ()>(&key)
+        .await
+        .map_err(|e| server_error(&e.into()))?;
+    let request: AuthorizationRequest = pending
+        .and_then(|v| serde_json::from_str(&v).ok())
+        .ok_or_else(|| Custom(Status::Unauthorized, json!("Unknown or expired login request")))?;
+
+    let metadata = oidc::discover(config)
+        .await
+        .map_err(|e| server_error(&(e as Box<dyn Error>)))?;
+    let id_token = oidc::exchange_code(config, &metadata, &code, &request.code_verifier)
+        .await
+        .map_err(login_error)?;
+    let claims = oidc::validate_id_token(config, &metadata, &id_token, &request.nonce)
+        .await
+        .map_err(login_error)?;
+    let role_codes = oidc::map_roles(config, &claims);
+
+    // Provisioned users can only log in through the IdP.
+    let password = auth::hash_password(auth::random_token(64))
+        .map_err(|e| server_error(&e.to_string().into()))?;
+    let session_roles = role_codes.clone();
+    let user = db
+        .run(move |c| provision_user(c, &claims, password, role_codes))
+        .await
+        .map_err(|e| match e {
+            TransactionError::Respond(response) => response,
+            TransactionError::Database(e) => server_error(&e.into()),
+        })?;
+
+    issue_session(
+        backend,
+        &mut cache,
+        &user,
+        &session_roles,
+        auth::random_token(128),
+        None,
+    )
+    .await
+}
+
+/// Matches the IdP identity to a user by subject, then by verified email,
+/// creating one if neither exists. Roles always follow the IdP groups.
+fn provision_user(
+    c: &PgConnection,
+    claims: &IdTokenClaims,
+    password: String,
+    role_codes: Vec<RoleCode>,
+) -> Result<User, TransactionError> {
+    c.transaction(|| {
+        let verified_email = claims.email.as_ref().filter(|_| claims.email_verified);
+        let user = match UserRepository::find_by_oidc_subject(c, &claims.sub) {
+            Ok(user) => user,
+            Err(NotFound) => match verified_email.map(|e| UserRepository::find_by_email(c, e)) {
+                Some(Ok(user)) => UserRepository::link_oidc_subject(c, user.id, &claims.sub)?,
+                Some(Err(NotFound)) | None => {
+                    let username = claims
+                        .preferred_username
+                        .clone()
+                        .or_else(|| verified_email.cloned())
+                        .unwrap_or_else(|| claims.sub.clone());
+                    // Never take over a local account by its username alone.
+                    match UserRepository::find_by_username(c, &username) {
+                        Ok(_) => {
+                            return Err(TransactionError::Respond(Custom(
+                                Status::Conflict,
+                                json!(format!(
+                                    "The username {} is taken by another account",
+                                    username
+                                )),
+                            )))
+                        }
+                        Err(NotFound) => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                    let new_user = NewUser {
+                        username,
+                        password,
+                        email: verified_email.cloned(),
+                        oidc_subject: Some(claims.sub.clone()),
+                        // A locale the IdP got wrong is no reason to refuse the login.
+                        locale: claims
+                            .extra
+                            .get("locale")
+                            .and_then(|l| l.as_str())
+                            .filter(|l| i18n::is_valid_locale(l))
+                            .map(|l| l.to_owned()),
+                    };
+                    return Ok(UserRepository::create(c, new_user, role_codes)?);
+                }
+                Some(Err(e)) => return Err(e.into()),
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        UserRepository::set_roles(c, &user, role_codes)?;
+        Ok(user)
+    })
+}
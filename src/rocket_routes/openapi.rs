@@ -0,0 +1,797 @@
+use rocket::fairing::AdHoc;
+use rocket::fs::{FileServer, Options};
+use rocket::response::content::RawHtml;
+use rocket::serde::json::{serde_json::json, Value};
+use rocket::Route;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+
+use crate::auth::Credentials;
+
+use super::v1::{
+    self, Crate, CrateWithRustacean, NewCrate, NewRustacean, Rustacean, RustaceanWithCrates,
+    UpdateCrate, UpdateRustacean,
+};
+
+enum Access {
+    Public,
+    User,
+    Editor,
+    Admin,
+}
+
+/// What the document says about one route, besides what the route itself
+/// tells: its method, path and parameters.
+struct Operation {
+    summary: &'static str,
+    tag: &'static str,
+    access: Access,
+    request: Option<Value>,
+    status: u16,
+    /// `None` for an empty response.
+    response: Option<Value>,
+    /// Besides the 401 of every protected route and the 500 of any route.
+    errors: &'static [u16],
+}
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    json!(gen.subschema_for::<T>())
+}
+
+/// A merge patch of `T`: any of its fields, where `null` clears an optional one.
+fn merge_patch_of<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    let mut schema = json!(T::json_schema(gen));
+    if let Some(schema) = schema.as_object_mut() {
+        schema.remove("required");
+        schema.remove("title");
+    }
+    schema
+}
+
+/// A resource with or without what `?include=` embeds.
+fn any_of(schemas: Vec<Value>) -> Value {
+    json!({ "anyOf": schemas })
+}
+
+fn array_of(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// Resources that have no `v1` type yet are described as free-form objects.
+fn object() -> Value {
+    json!({ "type": "object" })
+}
+
+fn bulk_results() -> Value {
+    json!({ "$ref": "#/components/schemas/BulkResults" })
+}
+
+/// A list that `Accept: text/csv` or `application/x-ndjson` exports instead,
+/// as a `content` object rather than a schema.
+fn exportable(list: Value) -> Value {
+    json!({
+        "content": {
+            "application/json": { "schema": list },
+            "text/csv": { "schema": { "type": "string" } },
+            "application/x-ndjson": { "schema": { "type": "string" } },
+        },
+    })
+}
+
+/// The body of the import routes, as a `content` object.
+fn records() -> Value {
+    json!({
+        "content": {
+            "text/csv": { "schema": { "type": "string" } },
+            "application/x-ndjson": { "schema": { "type": "string" } },
+        },
+    })
+}
+
+fn import_report() -> Value {
+    json!({ "$ref": "#/components/schemas/ImportReport" })
+}
+
+fn token() -> Value {
+    json!({ "$ref": "#/components/schemas/Token" })
+}
+
+fn op(
+    summary: &'static str,
+    tag: &'static str,
+    access: Access,
+    request: Option<Value>,
+    status: u16,
+    response: Option<Value>,
+    errors: &'static [u16],
+) -> Operation {
+    Operation {
+        summary,
+        tag,
+        access,
+        request,
+        status,
+        response,
+        errors,
+    }
+}
+
+fn operation(name: &str, gen: &mut SchemaGenerator) -> Option<Operation> {
+    Some(match name {
+        "login" => op(
+            "Log in and get a bearer token",
+            "auth",
+            Access::Public,
+            Some(schema::<Credentials>(gen)),
+            200,
+            Some(token()),
+            &[401, 403],
+        ),
+        "logout" => op("Revoke the current token", "auth", Access::User, None, 204, None, &[]),
+        "me" => op("The current user", "auth", Access::User, None, 200, Some(object()), &[]),
+        "get_rustaceans" => op(
+            "List rustaceans",
+            "rustaceans",
+            Access::Editor,
+            None,
+            200,
+            Some(exportable(array_of(any_of(vec![
+                schema::<Rustacean>(gen),
+                schema::<RustaceanWithCrates>(gen),
+            ])))),
+            &[422],
+        ),
+        "view_rustacean" => op(
+            "Get a rustacean",
+            "rustaceans",
+            Access::Editor,
+            None,
+            200,
+            Some(any_of(vec![
+                schema::<Rustacean>(gen),
+                schema::<RustaceanWithCrates>(gen),
+            ])),
+            &[404, 422],
+        ),
+        "create_rustacean" => op(
+            "Create a rustacean",
+            "rustaceans",
+            Access::Editor,
+            Some(schema::<NewRustacean>(gen)),
+            201,
+            Some(schema::<Rustacean>(gen)),
+            &[409, 422],
+        ),
+        "create_rustaceans" => op(
+            "Create rustaceans in bulk",
+            "rustaceans",
+            Access::Editor,
+            Some(array_of(schema::<NewRustacean>(gen))),
+            201,
+            Some(bulk_results()),
+            &[422],
+        ),
+        "update_rustaceans" => op(
+            "Replace rustaceans in bulk",
+            "rustaceans",
+            Access::Editor,
+            Some(array_of(schema::<UpdateRustacean>(gen))),
+            200,
+            Some(bulk_results()),
+            &[422],
+        ),
+        "import_rustaceans" => op(
+            "Create or update rustaceans by email from CSV or NDJSON",
+            "rustaceans",
+            Access::Editor,
+            Some(records()),
+            200,
+            Some(import_report()),
+            &[413, 415, 422],
+        ),
+        "update_rustacean" => op(
+            "Replace a rustacean",
+            "rustaceans",
+            Access::Editor,
+            Some(schema::<UpdateRustacean>(gen)),
+            200,
+            Some(schema::<Rustacean>(gen)),
+            &[404, 412, 422],
+        ),
+        "patch_rustacean" => op(
+            "Update some fields of a rustacean",
+            "rustaceans",
+            Access::Editor,
+            Some(merge_patch_of::<UpdateRustacean>(gen)),
+            200,
+            Some(schema::<Rustacean>(gen)),
+            &[404, 412, 422],
+        ),
+        "delete_rustacean" => op(
+            "Delete a rustacean",
+            "rustaceans",
+            Access::Editor,
+            None,
+            204,
+            None,
+            &[404, 409, 412],
+        ),
+        "get_crates" => op(
+            "List crates",
+            "crates",
+            Access::Editor,
+            None,
+            200,
+            Some(exportable(array_of(any_of(vec![
+                schema::<Crate>(gen),
+                schema::<CrateWithRustacean>(gen),
+            ])))),
+            &[422],
+        ),
+        "view_crate" => op(
+            "Get a crate",
+            "crates",
+            Access::Editor,
+            None,
+            200,
+            Some(any_of(vec![
+                schema::<Crate>(gen),
+                schema::<CrateWithRustacean>(gen),
+            ])),
+            &[404, 422],
+        ),
+        "create_crate" => op(
+            "Create a crate",
+            "crates",
+            Access::Editor,
+            Some(schema::<NewCrate>(gen)),
+            201,
+            Some(schema::<Crate>(gen)),
+            &[409, 422],
+        ),
+        "create_crates" => op(
+            "Create crates in bulk",
+            "crates",
+            Access::Editor,
+            Some(array_of(schema::<NewCrate>(gen))),
+            201,
+            Some(bulk_results()),
+            &[422],
+        ),
+        "update_crates" => op(
+            "Replace crates in bulk",
+            "crates",
+            Access::Editor,
+            Some(array_of(schema::<UpdateCrate>(gen))),
+            200,
+            Some(bulk_results()),
+            &[422],
+        ),
+        "import_crates" => op(
+            "Create or update crates by code from CSV or NDJSON",
+            "crates",
+            Access::Editor,
+            Some(records()),
+            200,
+            Some(import_report()),
+            &[413, 415, 422],
+        ),
+        "update_crate" => op(
+            "Replace a crate",
+            "crates",
+            Access::Editor,
+            Some(schema::<UpdateCrate>(gen)),
+            200,
+            Some(schema::<Crate>(gen)),
+            &[404, 412, 422],
+        ),
+        "patch_crate" => op(
+            "Update some fields of a crate",
+            "crates",
+            Access::Editor,
+            Some(merge_patch_of::<UpdateCrate>(gen)),
+            200,
+            Some(schema::<Crate>(gen)),
+            &[404, 412, 422],
+        ),
+        "delete_crate" => op(
+            "Delete a crate",
+            "crates",
+            Access::Editor,
+            None,
+            204,
+            None,
+            &[404, 412],
+        ),
+        "delete_crates" => op(
+            "Delete crates in bulk",
+            "crates",
+            Access::Editor,
+            None,
+            200,
+            Some(json!({
+                "type": "object",
+                "required": ["deleted", "not_found"],
+                "properties": {
+                    "deleted": array_of(json!({ "type": "integer" })),
+                    "not_found": array_of(json!({ "type": "integer" })),
+                },
+            })),
+            &[404, 422],
+        ),
+        "get_users" => op(
+            "List users with their roles",
+            "users",
+            Access::Admin,
+            None,
+            200,
+            Some(array_of(object())),
+            &[],
+        ),
+        "view_user" => op(
+            "Get a user with their roles",
+            "users",
+            Access::Admin,
+            None,
+            200,
+            Some(object()),
+            &[404],
+        ),
+        "create_user" => op(
+            "Create a user",
+            "users",
+            Access::Admin,
+            Some(object()),
+            201,
+            Some(object()),
+            &[409, 422],
+        ),
+        "delete_user" => op("Delete a user", "users", Access::Admin, None, 204, None, &[]),
+        "grant_role" => op(
+            "Grant a role",
+            "users",
+            Access::Admin,
+            Some(object()),
+            200,
+            Some(object()),
+            &[404],
+        ),
+        "revoke_role" => op(
+            "Revoke a role",
+            "users",
+            Access::Admin,
+            None,
+            200,
+            Some(object()),
+            &[404],
+        ),
+        "suspend_user" => op(
+            "Suspend a user and revoke their sessions",
+            "users",
+            Access::Admin,
+            Some(object()),
+            200,
+            Some(object()),
+            &[404],
+        ),
+        "reinstate_user" => op(
+            "Reinstate a suspended user",
+            "users",
+            Access::Admin,
+            None,
+            200,
+            Some(object()),
+            &[404],
+        ),
+        "logout_user" => op(
+            "Revoke every session of a user",
+            "users",
+            Access::Admin,
+            None,
+            204,
+            None,
+            &[],
+        ),
+        "get_audit_log" => op(
+            "The latest audit entries",
+            "admin",
+            Access::Admin,
+            None,
+            200,
+            Some(array_of(object())),
+            &[],
+        ),
+        "impersonate" => op(
+            "Get a token for another user",
+            "admin",
+            Access::Admin,
+            None,
+            200,
+            Some(token()),
+            &[400, 403, 404],
+        ),
+        "end_impersonation" => op(
+            "End the current impersonation",
+            "admin",
+            Access::User,
+            None,
+            204,
+            None,
+            &[400],
+        ),
+        "preview" => op(
+            "Render the digest email as HTML",
+            "digest",
+            Access::Editor,
+            None,
+            200,
+            Some(json!({ "type": "string", "contentMediaType": "text/html" })),
+            &[422],
+        ),
+        "get_subscriptions" => op(
+            "List digest subscriptions",
+            "digest",
+            Access::User,
+            None,
+            200,
+            Some(array_of(object())),
+            &[],
+        ),
+        "create_subscription" => op(
+            "Subscribe to the digest",
+            "digest",
+            Access::User,
+            Some(object()),
+            201,
+            Some(object()),
+            &[403, 422],
+        ),
+        "update_subscription" => op(
+            "Change a digest subscription",
+            "digest",
+            Access::User,
+            Some(object()),
+            200,
+            Some(object()),
+            &[404, 422],
+        ),
+        "delete_subscription" => op(
+            "Delete a digest subscription",
+            "digest",
+            Access::User,
+            None,
+            204,
+            None,
+            &[404],
+        ),
+        "get_webhooks" => op(
+            "List webhooks",
+            "webhooks",
+            Access::Admin,
+            None,
+            200,
+            Some(array_of(object())),
+            &[],
+        ),
+        "create_webhook" => op(
+            "Register a webhook",
+            "webhooks",
+            Access::Admin,
+            Some(object()),
+            201,
+            Some(object()),
+            &[422],
+        ),
+        "delete_webhook" => op(
+            "Delete a webhook",
+            "webhooks",
+            Access::Admin,
+            None,
+            204,
+            None,
+            &[],
+        ),
+        "get_deliveries" => op(
+            "The delivery log of a webhook",
+            "webhooks",
+            Access::Admin,
+            None,
+            200,
+            Some(array_of(object())),
+            &[404],
+        ),
+        "ping_webhook" => op(
+            "Send a test ping",
+            "webhooks",
+            Access::Admin,
+            None,
+            200,
+            Some(object()),
+            &[404],
+        ),
+        _ => return None,
+    })
+}
+
+fn parameter(name: &str, location: &str) -> Value {
+    let schema = match name == "id" || name.ends_with("_id") {
+        true => json!({ "type": "integer", "format": "int32" }),
+        false => json!({ "type": "string" }),
+    };
+    json!({
+        "name": name,
+        "in": location,
+        "required": location == "path",
+        "schema": schema,
+    })
+}
+
+/// `/crates/<id>?<q>` becomes the OpenAPI path `/crates/{id}` with an `id`
+/// path parameter and an optional `q` query parameter.
+pub fn path_of(route: &Route) -> (String, Vec<Value>) {
+    let (path, query) = match route.uri.as_str().split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (route.uri.as_str(), None),
+    };
+    let mut parameters = vec![];
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|segment| match segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            Some(name) => {
+                let name = name.trim_end_matches("..");
+                parameters.push(parameter(name, "path"));
+                format!("{{{}}}", name)
+            }
+            None => segment.to_owned(),
+        })
+        .collect();
+    for name in query.into_iter().flat_map(|query| query.split('&')) {
+        let name = name.trim_start_matches('<').trim_end_matches('>');
+        parameters.push(parameter(name, "query"));
+    }
+    (segments.join("/"), parameters)
+}
+
+fn responses(operation: &Operation) -> Value {
+    let mut responses = json!({});
+    responses[operation.status.to_string()] = match &operation.response {
+        Some(Value::Object(schema)) if schema.contains_key("content") => json!({
+            "description": operation.summary,
+            "content": schema["content"],
+        }),
+        Some(schema) => {
+            let media_type = schema["contentMediaType"]
+                .as_str()
+                .unwrap_or("application/json");
+            json!({
+                "description": operation.summary,
+                "content": { media_type: { "schema": schema } },
+            })
+        }
+        None => json!({ "description": operation.summary }),
+    };
+    let mut errors = operation.errors.to_vec();
+    if !matches!(operation.access, Access::Public) {
+        errors.push(401);
+    }
+    errors.push(500);
+    for status in errors {
+        responses[status.to_string()] =
+            json!({ "$ref": format!("#/components/responses/{}", status) });
+    }
+    responses
+}
+
+/// The OpenAPI 3.1 document of `/api/v1`, built from `v1::routes()` and the
+/// `v1` types. Routes without an `operation` entry are left out, which the
+/// drift test in `tests/openapi.rs` catches.
+pub fn document() -> Value {
+    let settings = SchemaSettings::draft2019_09().with(|settings| {
+        settings.definitions_path = "#/components/schemas/".to_owned();
+    });
+    let mut gen = settings.into_generator();
+
+    let mut paths = json!({});
+    for route in v1::routes() {
+        let name = route.name.as_deref().unwrap_or_default();
+        let operation = match operation(name, &mut gen) {
+            Some(operation) => operation,
+            None => {
+                log::warn!("No OpenAPI operation for route {}", name);
+                continue;
+            }
+        };
+        let (path, parameters) = path_of(&route);
+
+        let mut item = json!({
+            "operationId": name,
+            "summary": operation.summary,
+            "tags": [operation.tag],
+            "responses": responses(&operation),
+        });
+        if !parameters.is_empty() {
+            item["parameters"] = json!(parameters);
+        }
+        if let Some(schema) = &operation.request {
+            let media_type = route
+                .format
+                .as_ref()
+                .map_or("application/json".to_owned(), |format| format.to_string());
+            let content = match schema.get("content") {
+                Some(content) => content.clone(),
+                None => json!({ media_type: { "schema": schema } }),
+            };
+            item["requestBody"] = json!({ "required": true, "content": content });
+        }
+        match operation.access {
+            Access::Public => item["security"] = json!([]),
+            Access::User => (),
+            Access::Editor => item["description"] = json!("Requires the editor or admin role."),
+            Access::Admin => item["description"] = json!("Requires the admin role."),
+        }
+        paths[path][route.method.as_str().to_lowercase()] = item;
+    }
+
+    let mut schemas = json!(gen.take_definitions());
+    schemas["Token"] = json!({
+        "type": "object",
+        "required": ["token"],
+        "properties": { "token": { "type": "string" } },
+    });
+    schemas["BulkResults"] = json!({
+        "description": "A result per item, in order. `207` when only some were written.",
+        "type": "object",
+        "required": ["results"],
+        "properties": {
+            "results": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["index", "status"],
+                    "properties": {
+                        "index": { "type": "integer" },
+                        "status": {
+                            "type": "integer",
+                            "description": "`201` or `200` when created or updated, `404` for \
+                                an unknown id, `422`, or `424` for a valid item held back by an \
+                                invalid one.",
+                        },
+                        "data": { "description": "The created or updated resource." },
+                        "errors": {
+                            "type": "object",
+                            "additionalProperties": array_of(json!({ "type": "string" })),
+                        },
+                    },
+                },
+            },
+        },
+    });
+    schemas["ImportReport"] = json!({
+        "description": "What became of each line. `207` when only the valid lines were \
+            written, `422` when none were for lack of `?mode=best_effort`.",
+        "type": "object",
+        "required": ["applied", "lines"],
+        "properties": {
+            "applied": {
+                "type": "boolean",
+                "description": "Whether anything was written, never on a dry run.",
+            },
+            "lines": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["line", "action"],
+                    "properties": {
+                        "line": { "type": "integer" },
+                        "action": {
+                            "type": "string",
+                            "enum": ["create", "update", "unchanged", "error"],
+                        },
+                        "id": { "type": "integer" },
+                        "changes": {
+                            "description": "`[old, new]` by field, for an update.",
+                            "type": "object",
+                            "additionalProperties": { "type": "array" },
+                        },
+                        "errors": {
+                            "type": "object",
+                            "additionalProperties": array_of(json!({ "type": "string" })),
+                        },
+                    },
+                },
+            },
+        },
+    });
+    schemas["Error"] = json!({
+        "description": "A message, an object with an `error` message or, for invalid \
+            fields, their messages by field.",
+        "oneOf": [
+            { "type": "string" },
+            {
+                "type": "object",
+                "required": ["error"],
+                "properties": { "error": { "type": "string" } },
+            },
+            {
+                "type": "object",
+                "required": ["errors"],
+                "properties": {
+                    "errors": {
+                        "type": "object",
+                        "additionalProperties": { "type": "array", "items": { "type": "string" } },
+                    },
+                },
+            },
+        ],
+    });
+
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": { "schema": { "$ref": "#/components/schemas/Error" } },
+            },
+        })
+    };
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Cr8s",
+            "version": "1",
+            "description": "Crates and the rustaceans who publish them.",
+        },
+        "servers": [{ "url": v1::PREFIX }],
+        "security": [{ "bearerAuth": [] }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearerAuth": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "The token from `POST /login`, an opaque session id or a JWT.",
+                },
+            },
+            "responses": {
+                "400": error("The request cannot be applied as it stands"),
+                "401": error("Missing, invalid or insufficient credentials"),
+                "403": error("Forbidden, e.g. a suspended account"),
+                "404": error("Not found"),
+                "409": error("Conflicts with an existing resource"),
+                "412": error("The resource no longer has the ETag in `If-Match`"),
+                "413": error("The request body is too large"),
+                "415": error("The request body is not in a supported format"),
+                "422": error("The request body or parameters are invalid"),
+                "500": error("Something went wrong"),
+            },
+        },
+    })
+}
+
+#[get("/openapi.json")]
+pub fn spec() -> Value {
+    document()
+}
+
+/// Swagger UI, pointed at `/openapi.json`. Its assets are served by
+/// `assets_fairing`.
+#[get("/docs")]
+pub fn docs() -> RawHtml<&'static str> {
+    RawHtml(include_str!("../../templates/docs/index.html"))
+}
+
+/// Where the Dockerfile unpacks `swagger-ui-dist`, unless `ROCKET_DOCS_ASSETS`
+/// says otherwise.
+pub const DOCS_ASSETS: &str = "/usr/local/share/swagger-ui";
+
+/// Serves the Swagger UI assets at `/docs/assets`.
+pub fn assets_fairing() -> AdHoc {
+    AdHoc::on_ignite("Docs assets", |rocket| async {
+        let dir = rocket
+            .figment()
+            .extract_inner::<String>("docs_assets")
+            .unwrap_or_else(|_| DOCS_ASSETS.to_owned());
+        rocket.mount("/docs/assets", FileServer::new(dir, Options::Missing))
+    })
+}
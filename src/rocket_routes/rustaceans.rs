@@ -1,32 +1,126 @@
-use rocket::http::Status;
+use std::collections::HashSet;
+
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::Connection as _;
+use rocket::data::{Data, Limits};
+use rocket::http::{ContentType, Status};
 use rocket::response::status::{Custom, NoContent};
 use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket_db_pools::Connection;
+use validator::Validate;
 
-use super::{server_error, EditorUser};
+use super::bulk;
+use super::catalog::{self, Export, Listing};
+use super::idempotency::{self, Attempt, IdempotencyKey};
+use super::v1::{
+    self, NewRustacean, Representation, Rustacean, RustaceanWithCrates, UpdateRustacean,
+};
+use super::{
+    body_error, etag, server_error, validation_error, weak_etag, Body, CacheConn, EditorUser,
+    IfMatch, Tagged, TransactionError,
+};
 use crate::diesel::result::Error::NotFound;
-use crate::models::{NewRustacean, Rustacean};
+use crate::models;
 use crate::repositories::RustaceanRepository;
 use crate::rocket_routes::DbConn;
+use crate::webhooks;
+
+fn write_error(e: TransactionError) -> Custom<Value> {
+    match e {
+        TransactionError::Respond(response) => response,
+        TransactionError::Database(NotFound) => {
+            Custom(Status::NotFound, json!("Rustacean not found"))
+        }
+        TransactionError::Database(DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            Custom(Status::Conflict, json!("Rustacean still has crates"))
+        }
+        TransactionError::Database(e) => server_error(&e.into()),
+    }
+}
+
+fn tagged(rustacean: models::Rustacean) -> Tagged {
+    Tagged {
+        etag: etag(rustacean.updated_at),
+        value: json!(Rustacean::from(rustacean)),
+    }
+}
 
-#[get("/rustaceans")]
-pub async fn get_rustaceans(db: DbConn, _user: EditorUser) -> Result<Value, Custom<Value>> {
-    db.run(|c| {
-        RustaceanRepository::find_multiple(c, 100)
-            .map(|rustacean| json!(rustacean))
+/// `?include=crates` embeds each rustacean's crates, and `?fields=` picks
+/// the fields of the rustaceans, e.g. `?fields=id,name`. With `Accept:
+/// text/csv` or `application/x-ndjson`, every rustacean is exported instead.
+#[get("/rustaceans?<include>&<fields>")]
+pub async fn get_rustaceans(
+    db: DbConn,
+    _user: EditorUser,
+    export: Export,
+    include: Option<String>,
+    fields: Option<String>,
+) -> Result<Listing, Custom<Value>> {
+    if let Some(format) = export.format(&include, &fields)? {
+        return Ok(catalog::export(db, format, crate::catalog::export_rustaceans));
+    }
+    let representation = Representation::parse(
+        include.as_deref(),
+        "crates",
+        fields.as_deref(),
+        &Rustacean::FIELDS,
+    )
+    .map_err(validation_error)?;
+    db.run(move |c| {
+        let rustaceans = match representation.include {
+            Some(_) => RustaceanRepository::find_multiple_with_crates(c, 100).map(|rustaceans| {
+                json!(rustaceans
+                    .into_iter()
+                    .map(RustaceanWithCrates::from)
+                    .collect::<Vec<_>>())
+            }),
+            None => RustaceanRepository::find_multiple(c, 100).map(|rustaceans| {
+                json!(rustaceans
+                    .into_iter()
+                    .map(Rustacean::from)
+                    .collect::<Vec<_>>())
+            }),
+        };
+        rustaceans
+            .map(|rustaceans| Listing::Json(representation.select(rustaceans)))
             .map_err(|_e| Custom(Status::InternalServerError, json!("Something went wrong")))
     })
     .await
 }
 
-#[get("/rustaceans/<id>")]
+/// Takes `?include=` and `?fields=` like `GET /rustaceans`.
+#[get("/rustaceans/<id>?<include>&<fields>")]
 pub async fn view_rustacean(
     db: DbConn,
     _user: EditorUser,
     id: i32,
-) -> Result<Value, Custom<Value>> {
+    include: Option<String>,
+    fields: Option<String>,
+) -> Result<Tagged, Custom<Value>> {
+    let representation = Representation::parse(
+        include.as_deref(),
+        "crates",
+        fields.as_deref(),
+        &Rustacean::FIELDS,
+    )
+    .map_err(validation_error)?;
     db.run(move |c| {
-        RustaceanRepository::find(&c, id)
-            .map(|rustacean| json!(rustacean))
+        let rustacean = match representation.include {
+            Some(_) => RustaceanRepository::find_with_crates(c, id).map(|(rustacean, crates)| {
+                let mut versions = vec![(rustacean.id, rustacean.updated_at)];
+                versions.extend(crates.iter().map(|a_crate| (a_crate.id, a_crate.updated_at)));
+                Tagged {
+                    etag: weak_etag(&versions),
+                    value: json!(RustaceanWithCrates::from((rustacean, crates))),
+                }
+            }),
+            None => RustaceanRepository::find(c, id).map(tagged),
+        };
+        rustacean
+            .map(|rustacean| Tagged {
+                etag: rustacean.etag,
+                value: representation.select(rustacean.value),
+            })
             .map_err(|e| match e {
                 NotFound => Custom(Status::NotFound, json!("Rustacean not found")),
                 _ => server_error(&e.into()),
@@ -35,45 +129,235 @@ pub async fn view_rustacean(
     .await
 }
 
+/// A retry with the same `Idempotency-Key` gets the first response again.
 #[post("/rustaceans", format = "json", data = "<new_rustacean>")]
 pub async fn create_rustacean(
+    db: DbConn,
+    mut cache: Connection<CacheConn>,
+    user: EditorUser,
+    idempotency_key: IdempotencyKey,
+    new_rustacean: Body<NewRustacean>,
+) -> Result<Custom<Tagged>, Custom<Value>> {
+    new_rustacean.validate().map_err(validation_error)?;
+    let attempt = match idempotency::begin(
+        &mut cache,
+        &user.0,
+        idempotency_key,
+        "POST /rustaceans",
+        &*new_rustacean,
+    )
+    .await?
+    {
+        Attempt::Replay(response) => return Ok(response),
+        attempt => attempt,
+    };
+
+    let result = db
+        .run(move |c| {
+            c.transaction(|| -> Result<Tagged, TransactionError> {
+                let rustacean =
+                    tagged(RustaceanRepository::create(c, new_rustacean.into_inner().into())?);
+                webhooks::enqueue(c, webhooks::RUSTACEAN_CREATED, rustacean.value.clone())?;
+                Ok(rustacean)
+            })
+            .map(|rustacean| Custom(Status::Created, rustacean))
+            .map_err(write_error)
+        })
+        .await;
+    idempotency::finish(&mut cache, attempt, &result).await;
+    result
+}
+
+/// Creates up to `bulk::MAX_ITEMS` rustaceans with a single insert, in one
+/// transaction, answering with a result per rustacean. With
+/// `?mode=all_or_nothing`, the default, one invalid rustacean means none is
+/// created; with `?mode=best_effort` the valid ones still are.
+#[post("/rustaceans/bulk?<mode>", format = "json", data = "<new_rustaceans>")]
+pub async fn create_rustaceans(
     db: DbConn,
     _user: EditorUser,
-    new_rustacean: Json<NewRustacean>,
+    mode: Option<String>,
+    new_rustaceans: Json<Vec<NewRustacean>>,
 ) -> Result<Custom<Value>, Custom<Value>> {
+    let mode = bulk::Mode::parse(mode.as_deref())?;
+    let new_rustaceans = new_rustaceans.into_inner();
+    bulk::check_size("items", new_rustaceans.len())?;
+    let errors: Vec<Option<bulk::ItemError>> = new_rustaceans
+        .iter()
+        .map(|new_rustacean| new_rustacean.validate().err().map(bulk::ItemError::from))
+        .collect();
+    bulk::check_items(mode, &errors)?;
+
+    let valid: Vec<models::NewRustacean> = new_rustaceans
+        .into_iter()
+        .zip(&errors)
+        .filter(|(_, errors)| errors.is_none())
+        .map(|(new_rustacean, _)| new_rustacean.into())
+        .collect();
     db.run(move |c| {
-        RustaceanRepository::create(c, new_rustacean.into_inner())
-            .map(|rustacean| Custom(Status::Created, json!(rustacean)))
-            .map_err(|_e| Custom(Status::InternalServerError, json!("Something went wrong")))
+        c.transaction(|| -> Result<Vec<Value>, TransactionError> {
+            let created: Vec<Value> = RustaceanRepository::create_multiple(c, valid)?
+                .into_iter()
+                .map(|rustacean| json!(Rustacean::from(rustacean)))
+                .collect();
+            for rustacean in &created {
+                webhooks::enqueue(c, webhooks::RUSTACEAN_CREATED, rustacean.clone())?;
+            }
+            Ok(created)
+        })
+        .map(|created| bulk::report(errors, created, Status::Created))
+        .map_err(write_error)
+    })
+    .await
+}
+
+/// Replaces up to `bulk::MAX_ITEMS` rustaceans, each given with its `id`,
+/// like `PUT /crates/bulk`.
+#[put("/rustaceans/bulk?<mode>", format = "json", data = "<updates>")]
+pub async fn update_rustaceans(
+    db: DbConn,
+    _user: EditorUser,
+    mode: Option<String>,
+    updates: Json<Vec<UpdateRustacean>>,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let mode = bulk::Mode::parse(mode.as_deref())?;
+    let updates = updates.into_inner();
+    bulk::check_size("items", updates.len())?;
+    db.run(move |c| {
+        c.transaction(|| {
+            let ids = updates.iter().filter_map(|update| update.id).collect();
+            let existing: HashSet<i32> =
+                RustaceanRepository::find_ids_for_update(c, ids)?.into_iter().collect();
+            let errors: Vec<Option<bulk::ItemError>> = updates
+                .iter()
+                .map(|update| {
+                    let mut errors = update.validate().err().unwrap_or_default();
+                    match update.id {
+                        None => errors.add("id", v1::required()),
+                        Some(id) if errors.is_empty() && !existing.contains(&id) => {
+                            return Some(bulk::ItemError::NotFound)
+                        }
+                        Some(_) => {}
+                    }
+                    bulk::item_error(errors)
+                })
+                .collect();
+            bulk::check_items(mode, &errors).map_err(TransactionError::Respond)?;
+
+            let mut updated = vec![];
+            for (update, _) in updates.into_iter().zip(&errors).filter(|(_, e)| e.is_none()) {
+                if let Some(id) = update.id {
+                    let rustacean = json!(Rustacean::from(RustaceanRepository::save(
+                        c,
+                        id,
+                        update.into()
+                    )?));
+                    webhooks::enqueue(c, webhooks::RUSTACEAN_UPDATED, rustacean.clone())?;
+                    updated.push(rustacean);
+                }
+            }
+            Ok(bulk::report(errors, updated, Status::Ok))
+        })
+        .map_err(write_error)
     })
     .await
 }
 
+/// Creates or updates rustaceans by `email` like `POST /crates/import`.
+#[post("/rustaceans/import?<dry_run>&<mode>", data = "<data>")]
+pub async fn import_rustaceans(
+    db: DbConn,
+    _user: EditorUser,
+    content_type: Option<&ContentType>,
+    limits: &Limits,
+    dry_run: Option<bool>,
+    mode: Option<String>,
+    data: Data<'_>,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let all_or_nothing = bulk::Mode::parse(mode.as_deref())? == bulk::Mode::AllOrNothing;
+    let dry_run = dry_run.unwrap_or(false);
+    let (format, input) = catalog::read_import(content_type, limits, data).await?;
+    let result = db
+        .run(move |c| {
+            crate::catalog::import_rustaceans(c, format, &input, dry_run, all_or_nothing)
+        })
+        .await;
+    catalog::report(result, dry_run)
+}
+
+/// Honours `If-Match`, answering `412` if the rustacean changed in the
+/// meantime.
 #[put("/rustaceans/<id>", format = "json", data = "<rustacean>")]
 pub async fn update_rustacean(
     db: DbConn,
     _user: EditorUser,
+    if_match: IfMatch,
     id: i32,
-    rustacean: Json<Rustacean>,
-) -> Result<Value, Custom<Value>> {
+    rustacean: Body<UpdateRustacean>,
+) -> Result<Tagged, Custom<Value>> {
+    v1::validate_update(&*rustacean, rustacean.id, id).map_err(validation_error)?;
     db.run(move |c| {
-        RustaceanRepository::save(c, id, rustacean.into_inner())
-            .map(|rustacean| json!(rustacean))
-            .map_err(|_e| Custom(Status::InternalServerError, json!("Something went wrong")))
+        c.transaction(|| {
+            let current = RustaceanRepository::find_for_update(c, id)?;
+            if_match.check(&etag(current.updated_at))?;
+            let rustacean =
+                tagged(RustaceanRepository::save(c, id, rustacean.into_inner().into())?);
+            webhooks::enqueue(c, webhooks::RUSTACEAN_UPDATED, rustacean.value.clone())?;
+            Ok(rustacean)
+        })
+        .map_err(write_error)
+    })
+    .await
+}
+
+/// Only the fields in the `application/merge-patch+json` body change.
+/// Honours `If-Match` like `PUT`.
+#[patch("/rustaceans/<id>", format = "application/merge-patch+json", data = "<patch>")]
+pub async fn patch_rustacean(
+    db: DbConn,
+    _user: EditorUser,
+    if_match: IfMatch,
+    id: i32,
+    patch: Json<Value>,
+) -> Result<Tagged, Custom<Value>> {
+    let patch = patch.into_inner();
+    db.run(move |c| {
+        c.transaction(|| {
+            let current = RustaceanRepository::find_for_update(c, id)?;
+            if_match.check(&etag(current.updated_at))?;
+            let update: UpdateRustacean = v1::patched(Rustacean::from(current), &patch)
+                .map_err(|errors| TransactionError::Respond(body_error(errors)))?;
+            v1::validate_update(&update, update.id, id)
+                .map_err(|errors| TransactionError::Respond(validation_error(errors)))?;
+            let rustacean = tagged(RustaceanRepository::save(c, id, update.into())?);
+            webhooks::enqueue(c, webhooks::RUSTACEAN_UPDATED, rustacean.value.clone())?;
+            Ok(rustacean)
+        })
+        .map_err(write_error)
     })
     .await
 }
 
+/// Honours `If-Match` like `PUT`. Deleting a rustacean again answers `404`,
+/// which leaves things as they were, so a retried `DELETE` is safe. Their
+/// crates have to go first.
 #[delete("/rustaceans/<id>")]
 pub async fn delete_rustacean(
     db: DbConn,
     _user: EditorUser,
+    if_match: IfMatch,
     id: i32,
 ) -> Result<NoContent, Custom<Value>> {
     db.run(move |c| {
-        RustaceanRepository::delete(c, id)
-            .map(|_| NoContent)
-            .map_err(|_e| Custom(Status::InternalServerError, json!("Something went wrong")))
+        c.transaction(|| {
+            let current = RustaceanRepository::find_for_update(c, id)?;
+            if_match.check(&etag(current.updated_at))?;
+            RustaceanRepository::delete(c, id)?;
+            webhooks::enqueue(c, webhooks::RUSTACEAN_DELETED, json!({ "id": id }))?;
+            Ok(())
+        })
+        .map(|_| NoContent)
+        .map_err(write_error)
     })
     .await
 }
@@ -0,0 +1,190 @@
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::content::RawHtml;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket::State;
+
+use crate::auth;
+use crate::i18n;
+use crate::diesel::result::Error::NotFound;
+use crate::models::{
+    DigestSubscription, DigestSubscriptionChanges, NewDigestSubscription, RoleCode,
+};
+use crate::repositories::DigestSubscriptionRepository;
+
+use super::{server_error, DbConn, Principal};
+
+/// Signs unsubscribe links, from `ROCKET_DIGEST_SECRET`. The CLI signs with
+/// `DIGEST_SECRET`, so both must hold the same value.
+pub struct DigestSecret(pub String);
+
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Digest secret", |rocket| async {
+        if rocket.figment().find_value("digest_secret").is_err() {
+            return Ok(rocket);
+        }
+        match rocket.figment().extract_inner::<String>("digest_secret") {
+            Ok(secret) => Ok(rocket.manage(DigestSecret(secret))),
+            Err(e) => {
+                log::error!("Invalid digest secret: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+fn subscription_not_found(e: diesel::result::Error) -> Custom<Value> {
+    match e {
+        NotFound => Custom(Status::NotFound, json!("Subscription not found")),
+        _ => server_error(&e.into()),
+    }
+}
+
+fn validate_locale(locale: &Option<String>) -> Result<(), Custom<Value>> {
+    match locale {
+        Some(locale) if !i18n::is_valid_locale(locale) => Err(Custom(
+            Status::UnprocessableEntity,
+            json!("Invalid locale"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn is_editor(principal: &Principal) -> bool {
+    principal.has_any_role(&[RoleCode::Admin, RoleCode::Editor])
+}
+
+/// Anyone may manage their own subscriptions, editors manage everyone's
+/// including those of rustaceans.
+fn can_manage(principal: &Principal, subscription: &DigestSubscription) -> bool {
+    is_editor(principal) || subscription.user_id == Some(principal.user_id)
+}
+
+#[get("/digest/subscriptions")]
+pub async fn get_subscriptions(db: DbConn, principal: Principal) -> Result<Value, Custom<Value>> {
+    db.run(move |c| {
+        let subscriptions = match is_editor(&principal) {
+            true => DigestSubscriptionRepository::find_multiple(c, 100),
+            false => DigestSubscriptionRepository::find_by_user_id(c, principal.user_id),
+        };
+        subscriptions
+            .map(|subscriptions| json!(subscriptions))
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+/// Without `user_id` or `rustacean_id` the caller subscribes themselves.
+#[post("/digest/subscriptions", format = "json", data = "<new_subscription>")]
+pub async fn create_subscription(
+    db: DbConn,
+    principal: Principal,
+    new_subscription: Json<NewDigestSubscription>,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let mut new_subscription = new_subscription.into_inner();
+    if new_subscription.user_id.is_none() && new_subscription.rustacean_id.is_none() {
+        new_subscription.user_id = Some(principal.user_id);
+    }
+    if new_subscription.user_id.is_some() && new_subscription.rustacean_id.is_some() {
+        return Err(Custom(
+            Status::UnprocessableEntity,
+            json!("Subscribe either a user or a rustacean, not both"),
+        ));
+    }
+    if new_subscription.user_id != Some(principal.user_id) && !is_editor(&principal) {
+        return Err(Custom(Status::Forbidden, json!("Cannot subscribe someone else")));
+    }
+    validate_locale(&new_subscription.locale)?;
+
+    db.run(move |c| {
+        DigestSubscriptionRepository::create(c, new_subscription)
+            .map(|subscription| Custom(Status::Created, json!(subscription)))
+            .map_err(|e| match e {
+                DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Custom(
+                    Status::UnprocessableEntity,
+                    json!("Unknown user or rustacean"),
+                ),
+                _ => server_error(&e.into()),
+            })
+    })
+    .await
+}
+
+#[put("/digest/subscriptions/<id>", format = "json", data = "<changes>")]
+pub async fn update_subscription(
+    db: DbConn,
+    principal: Principal,
+    id: i32,
+    changes: Json<DigestSubscriptionChanges>,
+) -> Result<Value, Custom<Value>> {
+    validate_locale(&changes.locale)?;
+
+    db.run(move |c| -> Result<Value, Custom<Value>> {
+        let subscription =
+            DigestSubscriptionRepository::find(c, id).map_err(subscription_not_found)?;
+        if !can_manage(&principal, &subscription) {
+            return Err(Custom(Status::NotFound, json!("Subscription not found")));
+        }
+        DigestSubscriptionRepository::save(c, id, changes.into_inner())
+            .map(|subscription| json!(subscription))
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+#[delete("/digest/subscriptions/<id>")]
+pub async fn delete_subscription(
+    db: DbConn,
+    principal: Principal,
+    id: i32,
+) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| -> Result<NoContent, Custom<Value>> {
+        let subscription =
+            DigestSubscriptionRepository::find(c, id).map_err(subscription_not_found)?;
+        if !can_manage(&principal, &subscription) {
+            return Err(Custom(Status::NotFound, json!("Subscription not found")));
+        }
+        DigestSubscriptionRepository::delete(c, id)
+            .map(|_| NoContent)
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+fn verify_token(secret: Option<&State<DigestSecret>>, token: &str) -> Result<i32, Custom<Value>> {
+    let secret = secret.ok_or_else(|| Custom(Status::NotFound, json!("Not found")))?;
+    auth::verify_unsubscribe_token(&secret.0, token)
+        .ok_or_else(|| Custom(Status::NotFound, json!("Invalid unsubscribe link")))
+}
+
+/// Link scanners follow GET requests, so the link only shows a confirmation.
+#[get("/digest/unsubscribe?<token>")]
+pub fn confirm_unsubscribe(
+    secret: Option<&State<DigestSecret>>,
+    token: String,
+) -> Result<RawHtml<String>, Custom<Value>> {
+    verify_token(secret, &token)?;
+    Ok(RawHtml(format!(
+        "<form method=\"post\" action=\"/digest/unsubscribe?token={}\">\
+         <button type=\"submit\">Unsubscribe from the Cr8s digest</button></form>",
+        token
+    )))
+}
+
+/// Also the RFC 8058 one-click target of the `List-Unsubscribe` header.
+#[post("/digest/unsubscribe?<token>")]
+pub async fn unsubscribe(
+    db: DbConn,
+    secret: Option<&State<DigestSecret>>,
+    token: String,
+) -> Result<NoContent, Custom<Value>> {
+    let id = verify_token(secret, &token)?;
+
+    // Unsubscribing twice is not an error.
+    db.run(move |c| DigestSubscriptionRepository::delete(c, id))
+        .await
+        .map(|_| NoContent)
+        .map_err(|e| server_error(&e.into()))
+}
@@ -0,0 +1,213 @@
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket::State;
+use rocket_db_pools::Connection;
+use serde::Deserialize;
+
+use crate::auth;
+use crate::i18n;
+use crate::diesel::result::Error::NotFound;
+use crate::models::{NewUser, Role, RoleCode, User};
+use crate::repositories::{RoleRepository, UserRepository};
+use crate::rocket_routes::DbConn;
+
+use super::authorization::revoke_user_sessions;
+use super::{server_error, AdminUser, CacheConn, SessionBackend};
+
+#[derive(Deserialize)]
+pub struct NewUserWithRoles {
+    pub username: String,
+    pub password: String,
+    pub roles: Vec<RoleCode>,
+    pub locale: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleGrant {
+    pub role: RoleCode,
+}
+
+#[derive(Deserialize)]
+pub struct Suspension {
+    pub reason: Option<String>,
+}
+
+fn user_with_roles(user: User, roles: Vec<Role>) -> Value {
+    let mut value = json!(user);
+    value["roles"] = json!(roles);
+    value
+}
+
+fn user_not_found(e: diesel::result::Error) -> Custom<Value> {
+    match e {
+        NotFound => Custom(Status::NotFound, json!("User not found")),
+        _ => server_error(&e.into()),
+    }
+}
+
+#[get("/users")]
+pub async fn get_users(db: DbConn, _user: AdminUser) -> Result<Value, Custom<Value>> {
+    db.run(|c| {
+        UserRepository::find_with_roles(c)
+            .map(|users| {
+                json!(users
+                    .into_iter()
+                    .map(|(user, roles)| {
+                        user_with_roles(user, roles.into_iter().map(|(_, role)| role).collect())
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+#[get("/users/<id>")]
+pub async fn view_user(db: DbConn, _user: AdminUser, id: i32) -> Result<Value, Custom<Value>> {
+    db.run(move |c| -> Result<Value, Custom<Value>> {
+        let user = UserRepository::find(c, id).map_err(user_not_found)?;
+        let roles = RoleRepository::find_by_user(c, &user).map_err(|e| server_error(&e.into()))?;
+        Ok(user_with_roles(user, roles))
+    })
+    .await
+}
+
+#[post("/users", format = "json", data = "<new_user>")]
+pub async fn create_user(
+    db: DbConn,
+    _user: AdminUser,
+    new_user: Json<NewUserWithRoles>,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let new_user = new_user.into_inner();
+    if let Some(locale) = &new_user.locale {
+        if !i18n::is_valid_locale(locale) {
+            return Err(Custom(Status::UnprocessableEntity, json!("Invalid locale")));
+        }
+    }
+    let password =
+        auth::hash_password(new_user.password).map_err(|e| server_error(&e.to_string().into()))?;
+    let user = NewUser {
+        username: new_user.username,
+        password,
+        email: None,
+        oidc_subject: None,
+        locale: new_user.locale,
+    };
+
+    db.run(move |c| -> Result<Custom<Value>, Custom<Value>> {
+        let user = UserRepository::create(c, user, new_user.roles).map_err(|e| match e {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                Custom(Status::Conflict, json!("Username already taken"))
+            }
+            _ => server_error(&e.into()),
+        })?;
+        let roles = RoleRepository::find_by_user(c, &user).map_err(|e| server_error(&e.into()))?;
+        Ok(Custom(Status::Created, user_with_roles(user, roles)))
+    })
+    .await
+}
+
+#[delete("/users/<id>")]
+pub async fn delete_user(
+    db: DbConn,
+    _user: AdminUser,
+    backend: &State<SessionBackend>,
+    mut cache: Connection<CacheConn>,
+    id: i32,
+) -> Result<NoContent, Custom<Value>> {
+    revoke_user_sessions(backend, &mut cache, id).await?;
+
+    db.run(move |c| match UserRepository::delete(c, id) {
+        Ok(0) => Err(Custom(Status::NotFound, json!("User not found"))),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(server_error(&e.into())),
+    })
+    .await
+}
+
+#[post("/users/<id>/roles", format = "json", data = "<grant>")]
+pub async fn grant_role(
+    db: DbConn,
+    _user: AdminUser,
+    id: i32,
+    grant: Json<RoleGrant>,
+) -> Result<Value, Custom<Value>> {
+    db.run(move |c| -> Result<Value, Custom<Value>> {
+        let user = UserRepository::find(c, id).map_err(user_not_found)?;
+        UserRepository::add_role(c, &user, grant.into_inner().role)
+            .map_err(|e| server_error(&e.into()))?;
+        let roles = RoleRepository::find_by_user(c, &user).map_err(|e| server_error(&e.into()))?;
+        Ok(user_with_roles(user, roles))
+    })
+    .await
+}
+
+#[delete("/users/<id>/roles/<role>")]
+pub async fn revoke_role(
+    db: DbConn,
+    _user: AdminUser,
+    id: i32,
+    role: String,
+) -> Result<Value, Custom<Value>> {
+    let role_code = RoleCode::from_string(role)
+        .map_err(|_| Custom(Status::NotFound, json!("Role not found")))?;
+
+    db.run(move |c| -> Result<Value, Custom<Value>> {
+        let user = UserRepository::find(c, id).map_err(user_not_found)?;
+        match UserRepository::remove_role(c, &user, &role_code) {
+            Ok(_) | Err(NotFound) => {}
+            Err(e) => return Err(server_error(&e.into())),
+        }
+        let roles = RoleRepository::find_by_user(c, &user).map_err(|e| server_error(&e.into()))?;
+        Ok(user_with_roles(user, roles))
+    })
+    .await
+}
+
+#[post("/users/<id>/suspend", format = "json", data = "<suspension>")]
+pub async fn suspend_user(
+    db: DbConn,
+    _user: AdminUser,
+    backend: &State<SessionBackend>,
+    mut cache: Connection<CacheConn>,
+    id: i32,
+    suspension: Json<Suspension>,
+) -> Result<Value, Custom<Value>> {
+    let reason = suspension.into_inner().reason;
+    let user = db
+        .run(move |c| UserRepository::suspend(c, id, reason))
+        .await
+        .map_err(user_not_found)?;
+
+    revoke_user_sessions(backend, &mut cache, id).await?;
+    Ok(json!(user))
+}
+
+#[post("/users/<id>/reinstate")]
+pub async fn reinstate_user(db: DbConn, _user: AdminUser, id: i32) -> Result<Value, Custom<Value>> {
+    db.run(move |c| {
+        UserRepository::reinstate(c, id)
+            .map(|user| json!(user))
+            .map_err(user_not_found)
+    })
+    .await
+}
+
+#[post("/users/<id>/logout")]
+pub async fn logout_user(
+    db: DbConn,
+    _user: AdminUser,
+    backend: &State<SessionBackend>,
+    mut cache: Connection<CacheConn>,
+    id: i32,
+) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| UserRepository::find(c, id))
+        .await
+        .map_err(user_not_found)?;
+
+    revoke_user_sessions(backend, &mut cache, id)
+        .await
+        .map(|_| NoContent)
+}
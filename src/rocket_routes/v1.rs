@@ -0,0 +1,577 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method};
+use rocket::{Build, Request, Response, Rocket, Route};
+use schemars::gen::SchemaGenerator;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::models;
+
+pub const PREFIX: &str = "/api/v1";
+
+/// When the unversioned aliases of the v1 routes go away, as an HTTP-date,
+/// unless `ROCKET_ROOT_ALIAS_SUNSET` says otherwise.
+pub const ROOT_ALIAS_SUNSET: &str = "Fri, 31 Dec 2027 23:59:59 GMT";
+
+/// Crate codes follow crates.io: a letter, then letters, digits, `-` or `_`.
+static CRATE_CODE: Lazy<Regex> = Lazy::new(|| Regex::new("^[A-Za-z][A-Za-z0-9_-]*$").unwrap());
+
+/// The routes served under `/api/v1`. Their handlers speak the types below
+/// rather than the diesel models, so a column can change without changing the
+/// API, and an `/api/v2` can bring its own types and routes while v1 keeps
+/// answering as it always did.
+pub fn routes() -> Vec<Route> {
+    routes![
+        super::authorization::login,
+        super::authorization::logout,
+        super::authorization::me,
+        super::rustaceans::get_rustaceans,
+        super::rustaceans::view_rustacean,
+        super::rustaceans::create_rustacean,
+        super::rustaceans::create_rustaceans,
+        super::rustaceans::update_rustaceans,
+        super::rustaceans::import_rustaceans,
+        super::rustaceans::update_rustacean,
+        super::rustaceans::patch_rustacean,
+        super::rustaceans::delete_rustacean,
+        super::crates::get_crates,
+        super::crates::view_crate,
+        super::crates::create_crate,
+        super::crates::create_crates,
+        super::crates::update_crates,
+        super::crates::import_crates,
+        super::crates::update_crate,
+        super::crates::patch_crate,
+        super::crates::delete_crate,
+        super::crates::delete_crates,
+        super::users::get_users,
+        super::users::view_user,
+        super::users::create_user,
+        super::users::delete_user,
+        super::users::grant_role,
+        super::users::revoke_role,
+        super::users::suspend_user,
+        super::users::reinstate_user,
+        super::users::logout_user,
+        super::admin::get_audit_log,
+        super::admin::impersonate,
+        super::admin::end_impersonation,
+        super::digest::preview,
+        super::subscriptions::get_subscriptions,
+        super::subscriptions::create_subscription,
+        super::subscriptions::update_subscription,
+        super::subscriptions::delete_subscription,
+        super::webhooks::get_webhooks,
+        super::webhooks::create_webhook,
+        super::webhooks::delete_webhook,
+        super::webhooks::get_deliveries,
+        super::webhooks::ping_webhook,
+    ]
+}
+
+/// Marks responses served by the v1 routes mounted at `/` as deprecated,
+/// pointing at their `/api/v1` successor. Routes that only live at `/`, such
+/// as feeds and unsubscribe links, are left alone.
+pub struct RootAliases {
+    routes: Vec<(Method, String)>,
+}
+
+/// The `Sunset` of the root aliases, checked at ignite.
+struct RootAliasSunset(String);
+
+fn root_alias_sunset(rocket: &Rocket<Build>) -> Result<String, String> {
+    let sunset = match rocket.figment().find_value("root_alias_sunset") {
+        Ok(value) => value
+            .into_string()
+            .ok_or("root_alias_sunset is not a string")?,
+        Err(_) => ROOT_ALIAS_SUNSET.to_owned(),
+    };
+    let sunset = DateTime::parse_from_rfc2822(&sunset)
+        .map_err(|e| format!("root_alias_sunset {:?} is not an HTTP-date: {}", sunset, e))?;
+    Ok(sunset
+        .with_timezone(&Utc)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string())
+}
+
+impl Default for RootAliases {
+    fn default() -> Self {
+        RootAliases {
+            routes: routes()
+                .into_iter()
+                .map(|route| (route.method, route.uri.as_str().to_owned()))
+                .collect(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RootAliases {
+    fn info(&self) -> Info {
+        Info {
+            name: "Deprecated root aliases",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        match root_alias_sunset(&rocket) {
+            Ok(sunset) => Ok(rocket.manage(RootAliasSunset(sunset))),
+            Err(e) => {
+                log::error!("Invalid configuration: {}", e);
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let route = match request.route() {
+            Some(route) => route,
+            None => return,
+        };
+        let is_alias = self
+            .routes
+            .iter()
+            .any(|(method, uri)| *method == route.method && uri == route.uri.as_str());
+        if !is_alias {
+            return;
+        }
+
+        response.set_header(Header::new("Deprecation", "true"));
+        if let Some(RootAliasSunset(sunset)) = request.rocket().state() {
+            response.set_header(Header::new("Sunset", sunset.to_owned()));
+        }
+        response.set_header(Header::new(
+            "Link",
+            format!(
+                "<{}{}>; rel=\"successor-version\"",
+                PREFIX,
+                request.uri().path()
+            ),
+        ));
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Rustacean {
+    pub id: i32,
+    pub name: String,
+    pub email: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl Rustacean {
+    /// What `?fields=` can pick from.
+    pub const FIELDS: [&'static str; 4] = ["id", "name", "email", "created_at"];
+}
+
+impl From<models::Rustacean> for Rustacean {
+    fn from(rustacean: models::Rustacean) -> Self {
+        Rustacean {
+            id: rustacean.id,
+            name: rustacean.name,
+            email: rustacean.email,
+            created_at: rustacean.created_at,
+        }
+    }
+}
+
+/// An error on one field, for the checks `Validate` cannot express.
+pub fn field_error(
+    field: &'static str,
+    code: &'static str,
+    message: impl Into<Cow<'static, str>>,
+) -> ValidationErrors {
+    let mut error = ValidationError::new(code);
+    error.message = Some(message.into());
+    let mut errors = ValidationErrors::new();
+    errors.add(field, error);
+    errors
+}
+
+/// Applies an RFC 7386 merge patch: objects are merged, `null` removes a key
+/// and anything else replaces what was there.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            }
+            _ => merge_patch(target.entry(key.to_owned()).or_insert(Value::Null), value),
+        }
+    }
+}
+
+/// Merges `patch` into the current representation of a resource and reads
+/// the result back as the `PUT` body it amounts to.
+pub fn patched<T: DeserializeOwned + JsonSchema>(
+    current: impl Serialize,
+    patch: &Value,
+) -> Result<T, FieldErrors> {
+    let mut target = serde_json::to_value(current).map_err(|e| body_error(e.to_string()))?;
+    merge_patch(&mut target, patch);
+    parse(target)
+}
+
+/// Messages per field, like `validate()` gives, for a body that cannot even be
+/// deserialized.
+pub type FieldErrors = BTreeMap<String, Vec<String>>;
+
+/// A message about the body as a whole.
+pub fn body_error(message: impl Into<String>) -> FieldErrors {
+    FieldErrors::from([("body".to_owned(), vec![message.into()])])
+}
+
+fn describe(json_type: &str) -> String {
+    match json_type {
+        "integer" | "object" | "array" => format!("an {}", json_type),
+        "null" => "null".to_owned(),
+        _ => format!("a {}", json_type),
+    }
+}
+
+fn has_type(value: &Value, json_type: &str) -> bool {
+    match json_type {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// Deserializes a JSON object into `T`, reporting each missing field and each
+/// field of the wrong type, by `T`'s schema, rather than serde's first error.
+pub fn parse<T: DeserializeOwned + JsonSchema>(body: Value) -> Result<T, FieldErrors> {
+    let fields = match &body {
+        Value::Object(fields) => fields,
+        _ => return Err(body_error("must be a JSON object")),
+    };
+    let schema = serde_json::to_value(T::json_schema(&mut SchemaGenerator::default()))
+        .map_err(|e| body_error(e.to_string()))?;
+
+    let mut errors = FieldErrors::new();
+    let required = schema["required"].as_array().into_iter().flatten();
+    for field in required.filter_map(Value::as_str) {
+        if !fields.contains_key(field) {
+            errors.insert(field.to_owned(), vec!["is required".to_owned()]);
+        }
+    }
+    for (field, value) in fields {
+        let types: Vec<&str> = match &schema["properties"][field]["type"] {
+            Value::String(json_type) => vec![json_type],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => continue,
+        };
+        if !types.iter().any(|json_type| has_type(value, json_type)) {
+            let expected: Vec<String> = types.into_iter().map(describe).collect();
+            errors.insert(field.to_owned(), vec![format!("must be {}", expected.join(" or "))]);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    serde_json::from_value(body).map_err(|e| body_error(e.to_string()))
+}
+
+/// A field left out of a body that needs it.
+pub fn required() -> ValidationError {
+    let mut error = ValidationError::new("required");
+    error.message = Some("is required".into());
+    error
+}
+
+fn query_error(code: &'static str, message: String) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(message.into());
+    error
+}
+
+fn include(
+    include: Option<&str>,
+    relation: &'static str,
+) -> Result<Option<&'static str>, ValidationError> {
+    match include {
+        None => Ok(None),
+        Some(include) if include == relation => Ok(Some(relation)),
+        Some(_) => Err(query_error("include", format!("can only be {}", relation))),
+    }
+}
+
+fn fields(fields: Option<&str>, known: &[&str]) -> Result<Option<Vec<String>>, ValidationError> {
+    let fields: Vec<String> = match fields {
+        Some(fields) => fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        None => return Ok(None),
+    };
+    let unknown: Vec<&str> = fields
+        .iter()
+        .map(String::as_str)
+        .filter(|field| !known.contains(field))
+        .collect();
+    match unknown.is_empty() {
+        true => Ok(Some(fields)),
+        false => Err(query_error(
+            "fields",
+            format!("unknown fields {}", unknown.join(", ")),
+        )),
+    }
+}
+
+/// What a `GET` asked for with `?include=`, the key to embed related
+/// resources under, and `?fields=`, a comma-separated sparse fieldset.
+pub struct Representation {
+    pub include: Option<&'static str>,
+    pub fields: Option<Vec<String>>,
+}
+
+impl Representation {
+    /// `include` can only be `relation`, and `fields` only name `known` ones.
+    pub fn parse(
+        include: Option<&str>,
+        relation: &'static str,
+        fields: Option<&str>,
+        known: &[&str],
+    ) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let include = self::include(include, relation).unwrap_or_else(|error| {
+            errors.add("include", error);
+            None
+        });
+        let fields = self::fields(fields, known).unwrap_or_else(|error| {
+            errors.add("fields", error);
+            None
+        });
+        match errors.is_empty() {
+            true => Ok(Representation { include, fields }),
+            false => Err(errors),
+        }
+    }
+
+    /// Keeps the `fields` of a resource, or of each in a list, along with the
+    /// related resources it was asked to include.
+    pub fn select(&self, value: Value) -> Value {
+        let fields = match &self.fields {
+            Some(fields) => fields,
+            None => return value,
+        };
+        let select = |value: Value| match value {
+            Value::Object(object) => Value::Object(
+                object
+                    .into_iter()
+                    .filter(|(key, _)| fields.contains(key) || self.include == Some(key.as_str()))
+                    .collect(),
+            ),
+            value => value,
+        };
+        match value {
+            Value::Array(items) => Value::Array(items.into_iter().map(select).collect()),
+            value => select(value),
+        }
+    }
+}
+
+/// Validates a `PUT` body, whose `id` may be left out but must otherwise
+/// match the one in the URL.
+pub fn validate_update(
+    input: &impl Validate,
+    body_id: Option<i32>,
+    id: i32,
+) -> Result<(), ValidationErrors> {
+    let mut errors = input.validate().err().unwrap_or_default();
+    if body_id.is_some_and(|body_id| body_id != id) {
+        let mut error = ValidationError::new("mismatch");
+        error.message = Some("does not match the URL".into());
+        errors.add("id", error);
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Validate)]
+pub struct NewRustacean {
+    #[validate(length(min = 1, max = 128, message = "must be 1 to 128 characters"))]
+    pub name: String,
+    #[validate(email(message = "must be an email address"))]
+    pub email: String,
+}
+
+impl From<NewRustacean> for models::NewRustacean {
+    fn from(rustacean: NewRustacean) -> Self {
+        models::NewRustacean {
+            name: rustacean.name,
+            email: rustacean.email,
+        }
+    }
+}
+
+/// A rustacean with `?include=crates`.
+#[derive(Serialize, JsonSchema)]
+pub struct RustaceanWithCrates {
+    #[serde(flatten)]
+    pub rustacean: Rustacean,
+    pub crates: Vec<Crate>,
+}
+
+impl From<(models::Rustacean, Vec<models::Crate>)> for RustaceanWithCrates {
+    fn from((rustacean, crates): (models::Rustacean, Vec<models::Crate>)) -> Self {
+        RustaceanWithCrates {
+            rustacean: rustacean.into(),
+            crates: crates.into_iter().map(Crate::from).collect(),
+        }
+    }
+}
+
+/// Replaces a rustacean.
+#[derive(Deserialize, JsonSchema)]
+pub struct UpdateRustacean {
+    pub id: Option<i32>,
+    #[serde(flatten)]
+    pub rustacean: NewRustacean,
+}
+
+/// The fields are `NewRustacean`'s, and so are their errors: deriving would
+/// nest them under `rustacean`.
+impl Validate for UpdateRustacean {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.rustacean.validate()
+    }
+}
+
+impl From<UpdateRustacean> for models::NewRustacean {
+    fn from(update: UpdateRustacean) -> Self {
+        update.rustacean.into()
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Crate {
+    pub id: i32,
+    pub rustacean_id: i32,
+    pub code: String,
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl Crate {
+    /// What `?fields=` can pick from.
+    pub const FIELDS: [&'static str; 7] = [
+        "id",
+        "rustacean_id",
+        "code",
+        "name",
+        "version",
+        "description",
+        "created_at",
+    ];
+}
+
+impl From<models::Crate> for Crate {
+    fn from(a_crate: models::Crate) -> Self {
+        Crate {
+            id: a_crate.id,
+            rustacean_id: a_crate.rustacean_id,
+            code: a_crate.code,
+            name: a_crate.name,
+            version: a_crate.version,
+            description: a_crate.description,
+            created_at: a_crate.created_at,
+        }
+    }
+}
+
+/// A crate with `?include=rustacean`.
+#[derive(Serialize, JsonSchema)]
+pub struct CrateWithRustacean {
+    #[serde(flatten)]
+    pub a_crate: Crate,
+    pub rustacean: Rustacean,
+}
+
+impl From<(models::Crate, models::Rustacean)> for CrateWithRustacean {
+    fn from((a_crate, rustacean): (models::Crate, models::Rustacean)) -> Self {
+        CrateWithRustacean {
+            a_crate: a_crate.into(),
+            rustacean: rustacean.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Validate)]
+pub struct NewCrate {
+    pub rustacean_id: i32,
+    #[validate(
+        length(min = 1, max = 64, message = "must be 1 to 64 characters"),
+        regex(path = "CRATE_CODE", message = "must be a letter then letters, digits, - or _")
+    )]
+    pub code: String,
+    #[validate(length(min = 1, max = 128, message = "must be 1 to 128 characters"))]
+    pub name: String,
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"))]
+    pub version: String,
+    pub description: Option<String>,
+}
+
+impl From<NewCrate> for models::NewCrate {
+    fn from(a_crate: NewCrate) -> Self {
+        models::NewCrate {
+            rustacean_id: a_crate.rustacean_id,
+            code: a_crate.code,
+            name: a_crate.name,
+            version: a_crate.version,
+            description: a_crate.description,
+        }
+    }
+}
+
+/// Replaces a crate.
+#[derive(Deserialize, JsonSchema)]
+pub struct UpdateCrate {
+    pub id: Option<i32>,
+    #[serde(flatten)]
+    pub a_crate: NewCrate,
+}
+
+/// Like `UpdateRustacean`'s.
+impl Validate for UpdateCrate {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.a_crate.validate()
+    }
+}
+
+impl From<UpdateCrate> for models::NewCrate {
+    fn from(update: UpdateCrate) -> Self {
+        update.a_crate.into()
+    }
+}
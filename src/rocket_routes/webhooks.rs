@@ -0,0 +1,118 @@
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket::tokio::task;
+
+use crate::diesel::result::Error::NotFound;
+use crate::models::NewWebhook;
+use crate::repositories::{WebhookDeliveryRepository, WebhookRepository};
+use crate::webhooks;
+
+use super::{server_error, AdminUser, DbConn};
+
+fn webhook_not_found(e: diesel::result::Error) -> Custom<Value> {
+    match e {
+        NotFound => Custom(Status::NotFound, json!("Webhook not found")),
+        _ => server_error(&e.into()),
+    }
+}
+
+#[get("/webhooks")]
+pub async fn get_webhooks(db: DbConn, _user: AdminUser) -> Result<Value, Custom<Value>> {
+    db.run(|c| {
+        WebhookRepository::find_multiple(c, 100)
+            .map(|webhooks| json!(webhooks))
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+/// `events` takes event names such as `crate.created`, or patterns such as
+/// `rustacean.*`. The secret signs every payload and is never shown again.
+#[post("/webhooks", format = "json", data = "<new_webhook>")]
+pub async fn create_webhook(
+    db: DbConn,
+    _user: AdminUser,
+    new_webhook: Json<NewWebhook>,
+) -> Result<Custom<Value>, Custom<Value>> {
+    let new_webhook = new_webhook.into_inner();
+    match reqwest::Url::parse(&new_webhook.url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (),
+        _ => return Err(Custom(Status::UnprocessableEntity, json!("Invalid URL"))),
+    }
+    if new_webhook.secret.is_empty() {
+        return Err(Custom(Status::UnprocessableEntity, json!("Missing secret")));
+    }
+    webhooks::validate_events(&new_webhook.events)
+        .map_err(|e| Custom(Status::UnprocessableEntity, json!(e)))?;
+
+    db.run(move |c| {
+        WebhookRepository::create(c, new_webhook)
+            .map(|webhook| Custom(Status::Created, json!(webhook)))
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+#[delete("/webhooks/<id>")]
+pub async fn delete_webhook(
+    db: DbConn,
+    _user: AdminUser,
+    id: i32,
+) -> Result<NoContent, Custom<Value>> {
+    db.run(move |c| match WebhookRepository::delete(c, id) {
+        Ok(0) => Err(webhook_not_found(NotFound)),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(server_error(&e.into())),
+    })
+    .await
+}
+
+/// The delivery log, newest first.
+#[get("/webhooks/<id>/deliveries")]
+pub async fn get_deliveries(
+    db: DbConn,
+    _user: AdminUser,
+    id: i32,
+) -> Result<Value, Custom<Value>> {
+    db.run(move |c| -> Result<Value, Custom<Value>> {
+        WebhookRepository::find(c, id).map_err(webhook_not_found)?;
+        WebhookDeliveryRepository::find_by_webhook_id(c, id, 100)
+            .map(|deliveries| json!(deliveries))
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
+
+/// Sends a signed `ping` right away and answers with its delivery, so the
+/// receiving end can be checked without waiting for a real event. A failed
+/// ping is retried like any other delivery.
+#[post("/webhooks/<id>/ping")]
+pub async fn ping_webhook(
+    db: DbConn,
+    _user: AdminUser,
+    id: i32,
+) -> Result<Value, Custom<Value>> {
+    let (webhook, delivery) = db
+        .run(move |c| -> Result<_, Custom<Value>> {
+            let webhook = WebhookRepository::find(c, id).map_err(webhook_not_found)?;
+            let delivery =
+                webhooks::enqueue_ping(c, &webhook).map_err(|e| server_error(&e.into()))?;
+            Ok((webhook, delivery))
+        })
+        .await?;
+    // No database connection is held while waiting for the endpoint.
+    let (webhook, delivery, attempt) = task::spawn_blocking(move || {
+        let attempt = webhooks::send(&webhooks::client(), &delivery, &webhook);
+        (webhook, delivery, attempt)
+    })
+    .await
+    .map_err(|e| server_error(&e.into()))?;
+    db.run(move |c| {
+        webhooks::record(c, &delivery, &webhook, attempt)
+            .and_then(|_| WebhookDeliveryRepository::find(c, delivery.id))
+            .map(|delivery| json!(delivery))
+            .map_err(|e| server_error(&e.into()))
+    })
+    .await
+}
@@ -0,0 +1,202 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::thread;
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use diesel::sql_types::{BigInt, Bool};
+use diesel::{PgConnection, QueryResult, RunQueryDsl};
+use rocket::figment::providers::{Env, Format, Toml};
+use rocket::figment::Figment;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::commands;
+use crate::models::NewJobRun;
+use crate::repositories::JobRunRepository;
+
+sql_function!(fn pg_try_advisory_lock(key: BigInt) -> Bool);
+sql_function!(fn pg_advisory_unlock(key: BigInt) -> Bool);
+
+#[derive(Deserialize)]
+pub struct SchedulerConfig {
+    #[serde(default)]
+    pub jobs: Vec<JobConfig>,
+}
+
+#[derive(Deserialize)]
+pub struct JobConfig {
+    pub name: String,
+    /// Cron expression with a leading seconds field, evaluated in UTC,
+    /// e.g. `0 0 7 * * *` for every day at 07:00.
+    pub schedule: String,
+    #[serde(flatten)]
+    pub task: Task,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "task", rename_all = "kebab-case")]
+pub enum Task {
+    /// `digest-send --all`
+    Digest,
+    /// Drops expired sessions from the per-user session sets in Redis.
+    SessionCleanup,
+    /// Delivers the mail that is due, for setups without a queue worker.
+    MailQueue,
+    /// Delivers the webhook events that are due, likewise.
+    Webhooks,
+    /// Deletes audit entries, digest runs, job runs and finished webhook
+    /// deliveries older than `days`.
+    Retention {
+        #[serde(default = "default_retention_days")]
+        days: i64,
+    },
+}
+
+fn default_retention_days() -> i64 {
+    90
+}
+
+/// Reads the jobs from the file at `SCHEDULER_CONFIG` (`Scheduler.toml` by
+/// default), where `SCHEDULER_JOBS` in the environment overrides the file.
+pub fn load_config() -> SchedulerConfig {
+    let path = std::env::var("SCHEDULER_CONFIG").unwrap_or("Scheduler.toml".to_owned());
+    Figment::new()
+        .merge(Toml::file(path))
+        .merge(Env::prefixed("SCHEDULER_"))
+        .extract()
+        .unwrap_or_else(|e| panic!("Invalid scheduler configuration: {}", e))
+}
+
+struct ScheduledJob {
+    name: String,
+    schedule: Schedule,
+    task: Task,
+    next: Option<DateTime<Utc>>,
+}
+
+/// Runs the configured jobs forever. Every instance fires every job on time,
+/// but only the one that wins the job's advisory lock actually runs it.
+pub fn run(config: SchedulerConfig) {
+    let mut jobs: Vec<ScheduledJob> = config
+        .jobs
+        .into_iter()
+        .map(|job| {
+            let schedule = Schedule::from_str(&job.schedule)
+                .unwrap_or_else(|e| panic!("Invalid schedule for job {}: {}", job.name, e));
+            let next = schedule.upcoming(Utc).next();
+            ScheduledJob {
+                name: job.name,
+                schedule,
+                task: job.task,
+                next,
+            }
+        })
+        .collect();
+    if jobs.is_empty() {
+        println!("No jobs configured, nothing to schedule");
+        return;
+    }
+
+    let c = commands::load_db_connection();
+    loop {
+        let now = Utc::now();
+        for job in jobs.iter_mut() {
+            let scheduled_at = match job.next {
+                Some(next) if next <= now => next,
+                _ => continue,
+            };
+            run_job(&c, &job.name, &job.task, scheduled_at);
+            job.next = job.schedule.after(&Utc::now()).next();
+        }
+
+        let next = jobs.iter().filter_map(|job| job.next).min();
+        match next {
+            Some(next) => {
+                let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                thread::sleep(wait);
+            }
+            None => {
+                println!("No upcoming runs left, stopping");
+                return;
+            }
+        }
+    }
+}
+
+fn lock_key(job: &str) -> i64 {
+    let digest = Sha256::digest(format!("cr8s-job:{}", job).as_bytes());
+    i64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+fn run_job(c: &PgConnection, name: &str, task: &Task, scheduled_at: DateTime<Utc>) {
+    let key = lock_key(name);
+    match diesel::select(pg_try_advisory_lock(key)).get_result::<bool>(c) {
+        Ok(true) => {}
+        Ok(false) => {
+            println!("Job {} is running elsewhere, skipping", name);
+            return;
+        }
+        Err(e) => {
+            println!("Cannot lock job {}: {}", name, e);
+            return;
+        }
+    }
+
+    // Holding the lock isn't enough: another instance may have run this
+    // occurrence already and released the lock before we asked for it.
+    match JobRunRepository::find_last(c, name) {
+        Ok(Some(last)) if last.started_at >= scheduled_at.naive_utc() => {
+            println!("Job {} already ran at {}, skipping", name, last.started_at);
+        }
+        Ok(_) => {
+            if let Err(e) = record_run(c, name, task) {
+                println!("Cannot record run of job {}: {}", name, e);
+            }
+        }
+        Err(e) => println!("Cannot load the last run of job {}: {}", name, e),
+    }
+
+    if let Err(e) = diesel::select(pg_advisory_unlock(key)).get_result::<bool>(c) {
+        println!("Cannot unlock job {}: {}", name, e);
+    }
+}
+
+fn execute(task: &Task) {
+    match task {
+        Task::Digest => commands::send_all_digests(),
+        Task::SessionCleanup => commands::cleanup_sessions(),
+        Task::MailQueue => commands::work_mail_queue(true),
+        Task::Webhooks => commands::work_webhooks(true),
+        Task::Retention { days } => commands::apply_retention(*days),
+    }
+}
+
+fn record_run(c: &PgConnection, name: &str, task: &Task) -> QueryResult<()> {
+    let job_run = JobRunRepository::create(
+        c,
+        NewJobRun {
+            job: name.to_owned(),
+        },
+    )?;
+    println!("Running job {}", name);
+
+    // The commands behind the jobs panic on errors, that must not stop the
+    // scheduler.
+    let error = match panic::catch_unwind(AssertUnwindSafe(|| execute(task))) {
+        Ok(()) => None,
+        Err(panic) => Some(
+            panic
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or("Job panicked".to_owned()),
+        ),
+    };
+    match &error {
+        Some(e) => println!("Job {} failed: {}", name, e),
+        None => println!("Job {} succeeded", name),
+    }
+
+    JobRunRepository::finish(c, job_run.id, error).map(|_| ())
+}
@@ -1,5 +1,17 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Int4,
+        user_id -> Int4,
+        impersonator_id -> Nullable<Int4>,
+        method -> Varchar,
+        path -> Text,
+        status -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     crates (id) {
         id -> Int4,
@@ -9,6 +21,60 @@ diesel::table! {
         version -> Varchar,
         description -> Nullable<Text>,
         created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    digest_runs (id) {
+        id -> Int4,
+        recipient -> Varchar,
+        since -> Timestamp,
+        until -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    digest_subscriptions (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        rustacean_id -> Nullable<Int4>,
+        frequency -> Varchar,
+        owner_id -> Nullable<Int4>,
+        keyword -> Nullable<Varchar>,
+        last_sent_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        locale -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    job_runs (id) {
+        id -> Int4,
+        job -> Varchar,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        succeeded -> Nullable<Bool>,
+        error -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    outbound_emails (id) {
+        id -> Int4,
+        from_address -> Varchar,
+        to_address -> Varchar,
+        subject -> Text,
+        html_body -> Text,
+        text_body -> Nullable<Text>,
+        list_unsubscribe -> Nullable<Text>,
+        status -> Varchar,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        sent_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
     }
 }
 
@@ -27,6 +93,7 @@ diesel::table! {
         name -> Varchar,
         email -> Varchar,
         created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -36,6 +103,11 @@ diesel::table! {
         username -> Varchar,
         password -> Varchar,
         created_at -> Timestamp,
+        email -> Nullable<Varchar>,
+        oidc_subject -> Nullable<Varchar>,
+        disabled_at -> Nullable<Timestamp>,
+        disabled_reason -> Nullable<Text>,
+        locale -> Nullable<Varchar>,
     }
 }
 
@@ -47,8 +119,51 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        event -> Varchar,
+        payload -> Text,
+        status -> Varchar,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        response_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhooks (id) {
+        id -> Int4,
+        url -> Varchar,
+        secret -> Varchar,
+        events -> Array<Text>,
+        active -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::joinable!(crates -> rustaceans (rustacean_id));
+diesel::joinable!(digest_subscriptions -> rustaceans (rustacean_id));
+diesel::joinable!(digest_subscriptions -> users (user_id));
 diesel::joinable!(users_roles -> roles (role_id));
 diesel::joinable!(users_roles -> users (user_id));
+diesel::joinable!(webhook_deliveries -> webhooks (webhook_id));
 
-diesel::allow_tables_to_appear_in_same_query!(crates, roles, rustaceans, users, users_roles,);
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    crates,
+    digest_runs,
+    digest_subscriptions,
+    job_runs,
+    outbound_emails,
+    roles,
+    rustaceans,
+    users,
+    users_roles,
+    webhook_deliveries,
+    webhooks,
+);
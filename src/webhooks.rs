@@ -0,0 +1,221 @@
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+use diesel::{PgConnection, QueryResult};
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+
+use crate::auth;
+use crate::mail_queue::{self, Delivery};
+use crate::models::{NewWebhookDelivery, Webhook, WebhookDelivery};
+use crate::repositories::{WebhookDeliveryRepository, WebhookRepository};
+
+pub const CRATE_CREATED: &str = "crate.created";
+pub const CRATE_UPDATED: &str = "crate.updated";
+pub const CRATE_DELETED: &str = "crate.deleted";
+pub const RUSTACEAN_CREATED: &str = "rustacean.created";
+pub const RUSTACEAN_UPDATED: &str = "rustacean.updated";
+pub const RUSTACEAN_DELETED: &str = "rustacean.deleted";
+/// Only sent by the test-ping endpoint, whatever the webhook subscribed to.
+pub const PING: &str = "ping";
+
+pub const EVENTS: [&str; 6] = [
+    CRATE_CREATED,
+    CRATE_UPDATED,
+    CRATE_DELETED,
+    RUSTACEAN_CREATED,
+    RUSTACEAN_UPDATED,
+    RUSTACEAN_DELETED,
+];
+
+/// `crate.created` matches itself, `crate.*` and `*`.
+pub fn pattern_matches(pattern: &str, event: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => event.starts_with(prefix),
+        None => pattern == event,
+    }
+}
+
+/// Rejects patterns that could never match an event, which are typos.
+pub fn validate_events(events: &[String]) -> Result<(), String> {
+    if events.is_empty() {
+        return Err("Subscribe to at least one event".to_owned());
+    }
+    for pattern in events {
+        if !EVENTS.iter().any(|event| pattern_matches(pattern, event)) {
+            return Err(format!("Unknown event {}", pattern));
+        }
+    }
+    Ok(())
+}
+
+fn payload(event: &str, data: Value) -> String {
+    json!({
+        "event": event,
+        "created_at": Utc::now().naive_utc(),
+        "data": data,
+    })
+    .to_string()
+}
+
+/// Queues `event` for every active webhook that subscribed to it. Call it in
+/// the transaction that made the change, so an event goes out if and only if
+/// the change was committed.
+pub fn enqueue(c: &PgConnection, event: &str, data: Value) -> QueryResult<usize> {
+    let webhooks: Vec<Webhook> = WebhookRepository::find_active(c)?
+        .into_iter()
+        .filter(|webhook| webhook.events.iter().any(|p| pattern_matches(p, event)))
+        .collect();
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+
+    let payload = payload(event, data);
+    for webhook in &webhooks {
+        WebhookDeliveryRepository::create(
+            c,
+            NewWebhookDelivery {
+                webhook_id: webhook.id,
+                event: event.to_owned(),
+                payload: payload.to_owned(),
+            },
+        )?;
+    }
+    Ok(webhooks.len())
+}
+
+/// Queues a `ping` for one webhook, leased to the caller who delivers it
+/// straight away. The worker only retries it once the lease has run out.
+pub fn enqueue_ping(c: &PgConnection, webhook: &Webhook) -> QueryResult<WebhookDelivery> {
+    WebhookDeliveryRepository::create_leased(
+        c,
+        NewWebhookDelivery {
+            webhook_id: webhook.id,
+            event: PING.to_owned(),
+            payload: payload(PING, json!({ "webhook_id": webhook.id })),
+        },
+        lease_until(),
+    )
+}
+
+/// How long a claimed delivery is left to whoever claimed it.
+fn lease_until() -> chrono::NaiveDateTime {
+    Utc::now().naive_utc() + Duration::minutes(10)
+}
+
+pub fn client() -> Client {
+    Client::builder()
+        .timeout(StdDuration::from_secs(10))
+        .build()
+        .expect("Cannot build the webhook HTTP client")
+}
+
+/// The endpoint saying no, as opposed to being down or overloaded, is not
+/// worth retrying. Backoff and attempts are the mail queue's.
+fn is_permanent(status: reqwest::StatusCode) -> bool {
+    status.is_client_error()
+        && status != reqwest::StatusCode::REQUEST_TIMEOUT
+        && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// What one attempt at a delivery came to.
+pub enum Attempt {
+    Sent {
+        response_status: i32,
+    },
+    Failed {
+        response_status: Option<i32>,
+        error: String,
+        permanent: bool,
+    },
+}
+
+/// POSTs one delivery, signed. Touches no database, so that no connection is
+/// held during the round trip.
+pub fn send(client: &Client, delivery: &WebhookDelivery, webhook: &Webhook) -> Attempt {
+    let timestamp = Utc::now().timestamp();
+    let signature = auth::sign_webhook_payload(&webhook.secret, timestamp, &delivery.payload);
+    let response = client
+        .post(&webhook.url)
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "Cr8s-Webhooks")
+        .header("X-Cr8s-Event", &delivery.event)
+        .header("X-Cr8s-Delivery", delivery.id.to_string())
+        .header("X-Cr8s-Timestamp", timestamp.to_string())
+        .header("X-Cr8s-Signature", signature)
+        .body(delivery.payload.to_owned())
+        .send();
+
+    match response {
+        Ok(response) if response.status().is_success() => Attempt::Sent {
+            response_status: response.status().as_u16() as i32,
+        },
+        Ok(response) => Attempt::Failed {
+            response_status: Some(response.status().as_u16() as i32),
+            error: format!("Endpoint answered {}", response.status()),
+            permanent: is_permanent(response.status()),
+        },
+        Err(e) => Attempt::Failed {
+            response_status: None,
+            error: e.to_string(),
+            permanent: false,
+        },
+    }
+}
+
+/// Records the outcome of `send`. Returns whether the delivery was sent, or
+/// `None` if it is retried later.
+pub fn record(
+    c: &PgConnection,
+    delivery: &WebhookDelivery,
+    webhook: &Webhook,
+    attempt: Attempt,
+) -> QueryResult<Option<bool>> {
+    let (response_status, error, permanent) = match attempt {
+        Attempt::Sent { response_status } => {
+            WebhookDeliveryRepository::mark_sent(c, delivery.id, response_status)?;
+            return Ok(Some(true));
+        }
+        Attempt::Failed {
+            response_status,
+            error,
+            permanent,
+        } => (response_status, error, permanent),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let retry_at = mail_queue::next_attempt(attempts, permanent);
+    let what = format!("Webhook delivery {} to {}", delivery.id, webhook.url);
+    mail_queue::log_failure(&what, attempts, retry_at, &error);
+    WebhookDeliveryRepository::mark_failed(c, delivery.id, response_status, error, retry_at)?;
+    Ok(match retry_at {
+        Some(_) => None,
+        None => Some(false),
+    })
+}
+
+/// POSTs one delivery and records the outcome, like `record`.
+pub fn deliver(
+    c: &PgConnection,
+    client: &Client,
+    delivery: &WebhookDelivery,
+    webhook: &Webhook,
+) -> QueryResult<Option<bool>> {
+    let attempt = send(client, delivery, webhook);
+    record(c, delivery, webhook, attempt)
+}
+
+/// Sends up to `limit` due deliveries.
+pub fn deliver_due(c: &PgConnection, client: &Client, limit: i64) -> QueryResult<Delivery> {
+    let deliveries = WebhookDeliveryRepository::claim_due(c, limit, lease_until())?;
+
+    let mut outcome = Delivery::default();
+    for (delivery, webhook) in &deliveries {
+        match deliver(c, client, delivery, webhook)? {
+            Some(true) => outcome.sent += 1,
+            Some(false) => outcome.dead += 1,
+            None => outcome.retried += 1,
+        }
+    }
+    Ok(outcome)
+}
@@ -0,0 +1,164 @@
+use reqwest::{
+    blocking::{Client, ClientBuilder},
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+use serde_json::{json, Value};
+
+pub mod common;
+
+fn client_with_token(token: &str) -> Client {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(format!("Bearer {}", token).as_str()).unwrap(),
+    );
+    ClientBuilder::new()
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_impersonate() {
+    let admin_client = common::get_client_with_logged_in_admin();
+    let admin: Value = admin_client
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    let viewer: Value = common::get_client_with_logged_in_viewer()
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+
+    let response = admin_client
+        .post(format!(
+            "{}/admin/impersonate/{}",
+            common::APP_HOST,
+            viewer["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let client = client_with_token(json["token"].as_str().unwrap());
+
+    // The API behaves as it does for the viewer
+    let response = client.get(format!("{}/me", common::APP_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["username"], "test_viewer");
+    assert_eq!(json["impersonated_by"], admin["id"]);
+
+    let response = client
+        .get(format!("{}/crates", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Both identities end up in the audit log
+    let response = admin_client
+        .get(format!("{}/admin/audit", common::APP_HOST))
+        .send()
+        .unwrap();
+    let entries: Value = response.json().unwrap();
+    assert!(entries.as_array().unwrap().iter().any(|e| {
+        e["user_id"] == viewer["id"]
+            && e["impersonator_id"] == admin["id"]
+            && e["path"] == "/crates"
+    }));
+
+    let response = client
+        .delete(format!("{}/admin/impersonate", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client.get(format!("{}/me", common::APP_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_impersonate_requires_admin() {
+    let client = common::get_client_with_logged_in_editor();
+
+    let response = client
+        .post(format!("{}/admin/impersonate/1", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_impersonation_ends_with_suspended_admin() {
+    let admin_client = common::get_client_with_logged_in_admin();
+    let viewer: Value = common::get_client_with_logged_in_viewer()
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    let users: Value = admin_client
+        .get(format!("{}/users", common::APP_HOST))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    for user in users.as_array().unwrap() {
+        if user["username"] == "test_impersonating_admin" {
+            admin_client
+                .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+                .send()
+                .unwrap();
+        }
+    }
+    let response = admin_client
+        .post(format!("{}/users", common::APP_HOST))
+        .json(&json!({
+            "username": "test_impersonating_admin",
+            "password": "1234",
+            "roles": ["admin"],
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let other_admin: Value = response.json().unwrap();
+
+    let response = Client::new()
+        .post(format!("{}/login", common::APP_HOST))
+        .json(&json!({ "username": "test_impersonating_admin", "password": "1234" }))
+        .send()
+        .unwrap();
+    let json: Value = response.json().unwrap();
+    let response = client_with_token(json["token"].as_str().unwrap())
+        .post(format!(
+            "{}/admin/impersonate/{}",
+            common::APP_HOST,
+            viewer["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let client = client_with_token(json["token"].as_str().unwrap());
+    let response = client.get(format!("{}/me", common::APP_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Suspending the admin also ends the sessions they opened as someone else
+    let response = admin_client
+        .post(format!("{}/users/{}/suspend", common::APP_HOST, other_admin["id"]))
+        .json(&json!({ "reason": "Testing" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get(format!("{}/me", common::APP_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    admin_client
+        .delete(format!("{}/users/{}", common::APP_HOST, other_admin["id"]))
+        .send()
+        .unwrap();
+}
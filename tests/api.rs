@@ -0,0 +1,58 @@
+use reqwest::{blocking::Client, StatusCode};
+
+pub mod common;
+
+#[test]
+fn test_versioned_routes() {
+    let client = common::get_client_with_logged_in_admin();
+    let response = client
+        .get(format!("{}/api/v1/crates", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("Deprecation").is_none());
+    assert!(response.headers().get("Sunset").is_none());
+}
+
+#[test]
+fn test_root_aliases_are_deprecated() {
+    let client = common::get_client_with_logged_in_admin();
+    let response = client
+        .get(format!("{}/crates", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Deprecation"], "true");
+    assert_eq!(
+        response.headers()["Sunset"],
+        "Fri, 31 Dec 2027 23:59:59 GMT"
+    );
+    assert_eq!(
+        response.headers()["Link"],
+        "</api/v1/crates>; rel=\"successor-version\""
+    );
+
+    // Also when the alias fails.
+    let response = client
+        .get(format!("{}/crates/999999", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()["Deprecation"], "true");
+}
+
+#[test]
+fn test_unversioned_routes_are_not_deprecated() {
+    let response = Client::new()
+        .get(format!("{}/feeds/crates.atom", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("Deprecation").is_none());
+
+    let response = Client::new()
+        .get(format!("{}/api/v1/feeds/crates.atom", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -67,3 +67,19 @@ fn test_me() {
     assert!(json.get("created_at").is_some());
     assert!(json.get("password").is_none());
 }
+
+#[test]
+fn test_logout() {
+    let client = common::get_client_with_logged_in_viewer();
+
+    let response = client
+        .post(format!("{}/logout", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client.get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -1,4 +1,10 @@
-use reqwest::{blocking::Client, header::AUTHORIZATION, StatusCode};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::{
+    blocking::Client,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, IF_MATCH, IF_NONE_MATCH},
+    StatusCode,
+};
 use serde_json::{json, Value};
 
 pub mod common;
@@ -218,3 +224,703 @@ fn test_delete_crate() {
     assert_eq!(response.status(), StatusCode::NO_CONTENT);
     common::delete_test_rustacean(&client, rustacean);
 }
+
+#[test]
+fn test_create_crate_with_invalid_fields() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": "not a crate!",
+            "name": "",
+            "version": "0".repeat(65),
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+    let errors = json["errors"].as_object().unwrap();
+
+    assert_eq!(
+        errors.keys().collect::<Vec<_>>(),
+        vec!["code", "name", "version"]
+    );
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_update_crate_with_missing_and_mistyped_fields() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .put(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .json(&json!({
+            "rustacean_id": "one",
+            "code": "fooz",
+            "name": 42,
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+    let errors = json["errors"].as_object().unwrap();
+
+    assert_eq!(
+        errors.keys().collect::<Vec<_>>(),
+        vec!["name", "rustacean_id", "version"]
+    );
+    assert_eq!(json["errors"]["version"], json!(["is required"]));
+
+    // A body that is not JSON at all.
+    let response = client
+        .put(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header("Content-Type", "application/json")
+        .body("{")
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+
+    assert!(json["errors"]["body"].is_array());
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_crate_for_unknown_rustacean() {
+    let client = common::get_client_with_logged_in_admin();
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": 999999,
+            "code": "foo",
+            "name": "Foo",
+            "version": "0.1",
+            "description": null,
+        }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+
+    assert!(json["errors"]["rustacean_id"].is_array());
+}
+
+#[test]
+fn test_update_crate_with_another_id() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .put(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .json(&json!({
+            "id": a_crate["id"].as_i64().unwrap() + 1,
+            "rustacean_id": rustacean["id"],
+            "code": "fooz",
+            "name": "Fooz",
+            "version": "0.1.1",
+            "description": "fooz baz"
+        }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+
+    assert!(json["errors"]["id"].is_array());
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_patch_crate() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header(CONTENT_TYPE, "application/merge-patch+json")
+        .body(json!({ "description": "fixed typo" }).to_string())
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+    let mut expected = a_crate.clone();
+    expected["description"] = json!("fixed typo");
+
+    assert_eq!(json, expected);
+
+    // Removing a required field leaves no valid crate.
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header(CONTENT_TYPE, "application/merge-patch+json")
+        .body(json!({ "name": null }).to_string())
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = client
+        .patch(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header(CONTENT_TYPE, "application/merge-patch+json")
+        .body(json!({ "code": "not a crate!" }).to_string())
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_crate_etags() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let etag = response.headers()["etag"].to_str().unwrap().to_owned();
+
+    let response = client
+        .get(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header(IF_NONE_MATCH, &etag)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let update = json!({
+        "rustacean_id": rustacean["id"],
+        "code": "fooz",
+        "name": "Fooz",
+        "version": "0.1.1",
+        "description": "fooz baz"
+    });
+    let response = client
+        .put(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header(IF_MATCH, &etag)
+        .json(&update)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()["etag"].to_str().unwrap(), etag);
+
+    // The first save changed the crate, so a second one from the same ETag
+    // would overwrite it.
+    let response = client
+        .put(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header(IF_MATCH, &etag)
+        .json(&update)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .header(IF_MATCH, &etag)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_update_and_delete_missing_crate() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .put(format!("{}/crates/999999", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "code": "fooz",
+            "name": "Fooz",
+            "version": "0.1.1",
+            "description": "fooz baz"
+        }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .patch(format!("{}/crates/999999", common::APP_HOST))
+        .header(CONTENT_TYPE, "application/merge-patch+json")
+        .body(json!({ "name": "Fooz" }).to_string())
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .delete(format!("{}/crates/999999", common::APP_HOST))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_delete_crate_twice() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .delete(format!("{}/crates/{}", common::APP_HOST, a_crate["id"]))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_crate_with_idempotency_key() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let key = format!(
+        "test-{}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+    let body = json!({
+        "rustacean_id": rustacean["id"],
+        "code": "foo",
+        "name": "Foo",
+        "version": "0.1",
+        "description": null,
+    });
+
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .header("Idempotency-Key", &key)
+        .json(&body)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let a_crate: Value = response.json().unwrap();
+
+    // A retry gets the same crate rather than a second one.
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .header("Idempotency-Key", &key)
+        .json(&body)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.json::<Value>().unwrap(), a_crate);
+
+    let mut other_body = body.clone();
+    other_body["name"] = json!("Fooz");
+    let response = client
+        .post(format!("{}/crates", common::APP_HOST))
+        .header("Idempotency-Key", &key)
+        .json(&other_body)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_get_crates_with_rustacean_and_fields() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .get(format!(
+            "{}/crates?include=rustacean&fields=id,name",
+            common::APP_HOST
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert!(json.as_array().unwrap().contains(&json!({
+        "id": a_crate["id"],
+        "name": a_crate["name"],
+        "rustacean": rustacean,
+    })));
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}?include=rustacean",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut expected = a_crate.clone();
+    expected["rustacean"] = rustacean.clone();
+
+    assert_eq!(response.json::<Value>().unwrap(), expected);
+
+    let response = client
+        .get(format!(
+            "{}/crates/{}?include=owner&fields=id,colour",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+
+    assert!(json["errors"]["include"].is_array());
+    assert!(json["errors"]["fields"].is_array());
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_crates_in_bulk() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let items = json!([
+        {
+            "rustacean_id": rustacean["id"],
+            "code": "foo",
+            "name": "Foo",
+            "version": "0.1",
+            "description": null,
+        },
+        {
+            "rustacean_id": rustacean["id"],
+            "code": "not a crate!",
+            "name": "Bar",
+            "version": "0.1",
+            "description": null,
+        },
+    ]);
+
+    // One invalid crate and none is created.
+    let response = client
+        .post(format!("{}/crates/bulk", common::APP_HOST))
+        .json(&items)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["results"][0]["status"], 424);
+    assert_eq!(json["results"][1]["status"], 422);
+    assert!(json["results"][1]["errors"]["code"].is_array());
+
+    let response = client
+        .post(format!("{}/crates/bulk?mode=best_effort", common::APP_HOST))
+        .json(&items)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["results"][0]["status"], 201);
+    assert_eq!(json["results"][0]["data"]["code"], "foo");
+    assert_eq!(json["results"][1]["status"], 422);
+
+    let a_crate = json["results"][0]["data"].clone();
+    let response = client
+        .delete(format!(
+            "{}/crates?ids={},999999",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+
+    // All or nothing, so the crate that does exist is still there.
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .delete(format!(
+            "{}/crates?ids={},999999&mode=best_effort",
+            common::APP_HOST,
+            a_crate["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<Value>().unwrap(),
+        json!({ "deleted": [a_crate["id"]], "not_found": [999999] })
+    );
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_update_crates_in_bulk() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let items = json!([
+        {
+            "id": a_crate["id"],
+            "rustacean_id": rustacean["id"],
+            "code": "fooz",
+            "name": "Fooz",
+            "version": "0.2",
+            "description": null,
+        },
+        {
+            "id": 999999,
+            "rustacean_id": rustacean["id"],
+            "code": "bar",
+            "name": "Bar",
+            "version": "0.1",
+            "description": null,
+        },
+        {
+            "rustacean_id": rustacean["id"],
+            "code": "baz",
+            "name": "Baz",
+            "version": "0.1",
+            "description": null,
+        },
+    ]);
+
+    // An unknown id and nothing is updated.
+    let response = client
+        .put(format!("{}/crates/bulk", common::APP_HOST))
+        .json(&items)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["results"][0]["status"], 424);
+    assert_eq!(json["results"][1]["status"], 404);
+    assert_eq!(json["results"][2]["status"], 422);
+    assert!(json["results"][2]["errors"]["id"].is_array());
+
+    let response = client
+        .put(format!("{}/crates/bulk?mode=best_effort", common::APP_HOST))
+        .json(&items)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["results"][0]["status"], 200);
+    assert_eq!(json["results"][0]["data"]["code"], "fooz");
+    assert_eq!(json["results"][0]["data"]["id"], a_crate["id"]);
+    assert_eq!(json["results"][1]["status"], 404);
+    assert_eq!(json["results"][2]["status"], 422);
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_export_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .get(format!("{}/crates", common::APP_HOST))
+        .header(ACCEPT, "text/csv")
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/csv");
+
+    let csv = response.text().unwrap();
+    let mut lines = csv.lines();
+
+    assert_eq!(
+        lines.next(),
+        Some("id,rustacean_id,code,name,version,description,created_at")
+    );
+    assert!(lines.any(|line| line.starts_with(&format!("{},", a_crate["id"]))));
+
+    let response = client
+        .get(format!("{}/crates", common::APP_HOST))
+        .header(ACCEPT, "application/x-ndjson")
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let ndjson = response.text().unwrap();
+    let crates: Vec<Value> = ndjson
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert!(crates.contains(&a_crate));
+
+    // Exports have every field of a crate.
+    let response = client
+        .get(format!("{}/crates?fields=id", common::APP_HOST))
+        .header(ACCEPT, "text/csv")
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_import_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let code = format!(
+        "import-{}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+    let ndjson = format!(
+        "{}\n{}\n",
+        json!({
+            "rustacean_id": rustacean["id"],
+            "code": code,
+            "name": "Foo",
+            "version": "0.1",
+        }),
+        json!({
+            "rustacean_id": rustacean["id"],
+            "code": "not a crate!",
+            "name": "Bar",
+            "version": "0.1",
+        })
+    );
+    let import = |query: &str, content_type: &str, body: String| {
+        client
+            .post(format!("{}/crates/import{}", common::APP_HOST, query))
+            .header(CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .unwrap()
+    };
+
+    let response = import("?dry_run=true", "application/x-ndjson", ndjson.clone());
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["applied"], false);
+    assert_eq!(json["lines"][0]["line"], 1);
+    assert_eq!(json["lines"][0]["action"], "create");
+    assert_eq!(json["lines"][1]["line"], 2);
+    assert_eq!(json["lines"][1]["action"], "error");
+    assert!(json["lines"][1]["errors"]["code"].is_array());
+
+    // One invalid line and none is written.
+    let response = import("", "application/x-ndjson", ndjson.clone());
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.json::<Value>().unwrap()["applied"], false);
+
+    let response = import("?mode=best_effort", "application/x-ndjson", ndjson);
+
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["applied"], true);
+    assert_eq!(json["lines"][0]["action"], "create");
+
+    let id = json["lines"][0]["id"].clone();
+
+    // The crate is matched by its code, and only the name changed.
+    let csv = format!(
+        "rustacean_id,code,name,version,description\n{},{},Bar,0.1,\n",
+        rustacean["id"], code
+    );
+    let response = import("", "text/csv", csv);
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["lines"][0]["line"], 2);
+    assert_eq!(json["lines"][0]["action"], "update");
+    assert_eq!(json["lines"][0]["id"], id);
+    assert_eq!(json["lines"][0]["changes"], json!({ "name": ["Foo", "Bar"] }));
+
+    let response = import("", "application/json", "[]".to_owned());
+
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    common::delete_test_crate(&client, json!({ "id": id }));
+    common::delete_test_rustacean(&client, rustacean);
+}
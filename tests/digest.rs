@@ -0,0 +1,244 @@
+use std::process::Command;
+
+use chrono::{Duration, Utc};
+use cr8s::mail::MemoryMailTransport;
+use reqwest::StatusCode;
+
+pub mod common;
+
+#[test]
+fn test_send_digest_renders_new_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let since = Utc::now().naive_utc() - Duration::minutes(5);
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let transport = MemoryMailTransport::default();
+    cr8s::commands::send_digest_with(
+        Box::new(transport.clone()),
+        "digest@cr8s.com".to_owned(),
+        Some(since),
+        None,
+        None,
+    );
+
+    let messages = transport.messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].to, "digest@cr8s.com");
+    assert_eq!(messages[0].subject, "Cr8s digest");
+    let code = format!(
+        "<code>{} {}</code>",
+        a_crate["code"].as_str().unwrap(),
+        a_crate["version"].as_str().unwrap()
+    );
+    assert!(messages[0].html_body.contains(&code));
+    assert!(messages[0].html_body.contains("foo bar"));
+    let text_body = messages[0].text_body.as_ref().unwrap();
+    assert!(text_body.contains(a_crate["code"].as_str().unwrap()));
+    assert!(!text_body.contains("<code>"));
+    assert_eq!(
+        messages[0].list_unsubscribe.as_deref(),
+        Some("<mailto:info@cr8s.com?subject=Unsubscribe%20digest%40cr8s.com>")
+    );
+
+    // Cleanup
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_send_digest_localised() {
+    let client = common::get_client_with_logged_in_admin();
+    let since = Utc::now().naive_utc() - Duration::minutes(5);
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    // No catalog for de-AT, so it falls back to de.
+    let transport = MemoryMailTransport::default();
+    cr8s::commands::send_digest_with(
+        Box::new(transport.clone()),
+        "digest-de@cr8s.com".to_owned(),
+        Some(since),
+        None,
+        Some("de-AT".to_owned()),
+    );
+    let messages = transport.messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].subject, "Cr8s-Übersicht");
+    assert!(messages[0].html_body.contains("<html lang=\"de\""));
+    assert!(messages[0].html_body.contains("die zwischen"));
+    assert!(!messages[0].html_body.contains("24hours"));
+    assert!(messages[0].text_body.as_ref().unwrap().contains("Erstellt und versendet"));
+
+    // Nothing for pt-BR or pt, so it falls back to English.
+    let transport = MemoryMailTransport::default();
+    cr8s::commands::send_digest_with(
+        Box::new(transport.clone()),
+        "digest-pt@cr8s.com".to_owned(),
+        Some(since),
+        None,
+        Some("pt-BR".to_owned()),
+    );
+    let messages = transport.messages();
+    assert_eq!(messages[0].subject, "Cr8s digest");
+    assert!(messages[0].html_body.contains("created between"));
+
+    // Cleanup
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_send_digest_without_new_crates() {
+    let transport = MemoryMailTransport::default();
+    let until = Utc::now().naive_utc() - Duration::days(3650);
+    cr8s::commands::send_digest_with(
+        Box::new(transport.clone()),
+        "digest@cr8s.com".to_owned(),
+        Some(until - Duration::hours(1)),
+        Some(until),
+        None,
+    );
+
+    assert!(transport.messages().is_empty());
+}
+
+#[test]
+fn test_send_digest_to_maildir() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let maildir = std::env::temp_dir().join(format!("cr8s-test-{}", a_crate["id"]));
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("digest-send")
+        .arg("digest@cr8s.com")
+        .arg("--since")
+        .arg("5m")
+        .output()
+        .unwrap();
+    println!("{:?}", output);
+    assert!(output.status.success());
+
+    // The digest is only queued, the worker delivers it.
+    assert!(!maildir.exists());
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("mail-queue")
+        .arg("work")
+        .arg("--once")
+        .env("MAIL_TRANSPORT", "file")
+        .env("MAIL_DIR", &maildir)
+        .output()
+        .unwrap();
+    println!("{:?}", output);
+    assert!(output.status.success());
+
+    let files: Vec<_> = std::fs::read_dir(maildir.join("new"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert!(files.iter().all(|file| file.extension().unwrap() == "eml"));
+    // The worker also delivers whatever else was queued.
+    let eml = files
+        .iter()
+        .map(|file| std::fs::read_to_string(file).unwrap())
+        .find(|eml| eml.contains("To: digest@cr8s.com"))
+        .unwrap();
+    assert!(eml.contains("Subject: Cr8s digest"));
+    assert!(eml.contains("Content-Type: multipart/alternative"));
+    assert!(eml.contains("Content-Type: text/plain"));
+    assert!(eml.contains("List-Unsubscribe: <mailto:info@cr8s.com"));
+
+    // Cleanup
+    std::fs::remove_dir_all(maildir).unwrap();
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_preview_digest() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .get(format!("{}/digest/preview?since=5m", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+    let html = response.text().unwrap();
+    assert!(html.contains(&format!(
+        "<code>{} {}</code>",
+        a_crate["code"].as_str().unwrap(),
+        a_crate["version"].as_str().unwrap()
+    )));
+
+    let response = client
+        .get(format!("{}/digest/preview?since=yesterday", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let viewer_client = common::get_client_with_logged_in_viewer();
+    let response = viewer_client
+        .get(format!("{}/digest/preview", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Cleanup
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_send_digest_dry_run() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let output_file = std::env::temp_dir().join(format!("cr8s-preview-{}.html", a_crate["id"]));
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("digest-send")
+        .arg("dry-run@cr8s.com")
+        .arg("--since")
+        .arg("5m")
+        .arg("--dry-run")
+        .arg("--output")
+        .arg(&output_file)
+        .env("MAIL_TRANSPORT", "smtp")
+        .env_remove("SMTP_HOST")
+        .output()
+        .unwrap();
+    println!("{:?}", output);
+    assert!(output.status.success());
+
+    let html = std::fs::read_to_string(&output_file).unwrap();
+    assert!(html.contains(a_crate["code"].as_str().unwrap()));
+
+    // Nothing was recorded, a real run still covers the same window.
+    let transport = MemoryMailTransport::default();
+    cr8s::commands::send_digest_with(
+        Box::new(transport.clone()),
+        "dry-run@cr8s.com".to_owned(),
+        None,
+        None,
+        None,
+    );
+    assert_eq!(transport.messages().len(), 1);
+
+    // Cleanup
+    std::fs::remove_file(output_file).unwrap();
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
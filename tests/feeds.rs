@@ -0,0 +1,114 @@
+use reqwest::{blocking::Client, header, StatusCode};
+
+pub mod common;
+
+#[test]
+fn test_crates_feeds() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    // Public, no token needed.
+    let client = Client::new();
+    let response = client
+        .get(format!("{}/feeds/crates.atom", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/atom+xml; charset=utf-8"
+    );
+    let body = response.text().unwrap();
+    assert!(body.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+    assert!(body.contains(&format!("/crates/{}</id>", a_crate["id"])));
+
+    let response = client
+        .get(format!("{}/feeds/crates.rss", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/rss+xml; charset=utf-8"
+    );
+    let body = response.text().unwrap();
+    assert!(body.contains("<rss version=\"2.0\""));
+    assert!(body.contains("<title>Foo 0.1</title>"));
+
+    // Cleanup
+    let client = common::get_client_with_logged_in_admin();
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_rustacean_feed_conditional_requests() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let url = format!(
+        "{}/feeds/rustaceans/{}.atom",
+        common::APP_HOST,
+        rustacean["id"]
+    );
+
+    // An empty feed has no date to compare with.
+    let response = Client::new().get(&url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::LAST_MODIFIED).is_none());
+
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    let client = Client::new();
+    let response = client.get(&url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()[header::ETAG].to_owned();
+    let last_modified = response.headers()[header::LAST_MODIFIED].to_owned();
+    let body = response.text().unwrap();
+    assert!(body.contains("<title>Crates by Foo</title>"));
+    assert!(body.contains("<name>Foo</name>"));
+
+    let response = client
+        .get(&url)
+        .header(header::IF_NONE_MATCH, etag.to_owned())
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let response = client
+        .get(&url)
+        .header(header::IF_MODIFIED_SINCE, last_modified)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // A new crate changes the feed.
+    let admin = common::get_client_with_logged_in_admin();
+    let other_crate = common::create_test_crate(&admin, &rustacean);
+    let response = client
+        .get(&url)
+        .header(header::IF_NONE_MATCH, etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Cleanup
+    common::delete_test_crate(&admin, other_crate);
+    common::delete_test_crate(&admin, a_crate);
+    common::delete_test_rustacean(&admin, rustacean);
+}
+
+#[test]
+fn test_rustacean_feed_not_found() {
+    let client = Client::new();
+    let response = client
+        .get(format!("{}/feeds/rustaceans/999999.atom", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .get(format!("{}/feeds/rustaceans/foo.atom", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
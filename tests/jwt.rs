@@ -0,0 +1,301 @@
+use std::net::Ipv4Addr;
+use std::sync::Once;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::{
+    blocking::{Client, ClientBuilder},
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+use rocket_db_pools::Database;
+use serde_json::{json, Value};
+
+pub mod common;
+
+// JWT mode instances of the server, next to the session mode one on 8000.
+// The first key signs, so 8002 hands out HS256 tokens and 8003 EdDSA ones.
+static HS_HOST: &str = "http://127.0.0.1:8002";
+static EDDSA_HOST: &str = "http://127.0.0.1:8003";
+static SERVERS_STARTED: Once = Once::new();
+
+static HS_SECRET: &str = "cr8s-jwt-secret";
+static ED_PRIVATE_KEY: &str = include_str!("fixtures/jwt_ed25519.pem");
+static ED_PUBLIC_KEY: &str = include_str!("fixtures/jwt_ed25519.pub.pem");
+
+fn hs_key() -> Value {
+    json!({ "kid": "hs-current", "alg": "HS256", "secret": HS_SECRET })
+}
+
+fn eddsa_key() -> Value {
+    json!({
+        "kid": "ed-current",
+        "alg": "EdDSA",
+        "private_key": ED_PRIVATE_KEY,
+        "public_key": ED_PUBLIC_KEY,
+    })
+}
+
+fn start_server(port: u16, keys: Value) {
+    std::thread::spawn(move || {
+        let figment = rocket::Config::figment()
+            .merge(("address", Ipv4Addr::LOCALHOST.to_string()))
+            .merge(("port", port))
+            .merge(("log_level", "off"))
+            .merge(("auth", json!({ "mode": "jwt", "token_ttl": 900, "keys": keys })));
+        let _ = rocket::execute(
+            rocket::custom(figment)
+                .mount(
+                    "/",
+                    rocket::routes![
+                        cr8s::rocket_routes::authorization::login,
+                        cr8s::rocket_routes::authorization::logout,
+                        cr8s::rocket_routes::authorization::me,
+                        cr8s::rocket_routes::users::suspend_user,
+                    ],
+                )
+                .attach(cr8s::rocket_routes::DbConn::fairing())
+                .attach(cr8s::rocket_routes::CacheConn::init())
+                .attach(cr8s::rocket_routes::authorization::fairing())
+                .launch(),
+        );
+    });
+}
+
+fn start_servers() {
+    SERVERS_STARTED.call_once(|| {
+        start_server(8002, json!([hs_key(), eddsa_key()]));
+        start_server(8003, json!([eddsa_key(), hs_key()]));
+
+        for host in [HS_HOST, EDDSA_HOST] {
+            let started = (0..50).any(|_| {
+                if reqwest::blocking::get(format!("{}/me", host)).is_ok() {
+                    return true;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+                false
+            });
+            assert!(started, "JWT mode server {} did not start", host);
+        }
+    });
+}
+
+fn client_with_token(token: &str) -> Client {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(format!("Bearer {}", token).as_str()).unwrap(),
+    );
+    ClientBuilder::new()
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+/// Recreates a viewer through the session mode server, both share the database.
+fn create_test_user(username: &str) -> Value {
+    let client = common::get_client_with_logged_in_admin();
+    let users: Value = client
+        .get(format!("{}/users", common::APP_HOST))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    for user in users.as_array().unwrap() {
+        if user["username"] == username {
+            client
+                .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+                .send()
+                .unwrap();
+        }
+    }
+
+    let response = client
+        .post(format!("{}/users", common::APP_HOST))
+        .json(&json!({
+            "username": username,
+            "password": "1234",
+            "roles": ["viewer"],
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    response.json().unwrap()
+}
+
+fn delete_test_user(user: Value) {
+    common::get_client_with_logged_in_admin()
+        .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+}
+
+fn login(host: &str, username: &str) -> String {
+    let response = Client::new()
+        .post(format!("{}/login", host))
+        .json(&json!({ "username": username, "password": "1234" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    json["token"].as_str().unwrap().to_owned()
+}
+
+fn token_header(token: &str) -> Value {
+    let header = token.split('.').next().unwrap();
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap()
+}
+
+fn sign(kid: &str, alg: Algorithm, key: &EncodingKey, user: &Value) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let claims = json!({
+        "sub": user["id"].to_string(),
+        "roles": ["viewer"],
+        "jti": format!("test-{}-{}", kid, now),
+        "iat": now,
+        "exp": now + 300,
+    });
+    let mut header = Header::new(alg);
+    header.kid = Some(kid.to_owned());
+    encode(&header, &claims, key).unwrap()
+}
+
+#[test]
+fn test_jwt_login() {
+    start_servers();
+    let user = create_test_user("test_jwt_user");
+
+    for (host, kid, alg) in [
+        (HS_HOST, "hs-current", "HS256"),
+        (EDDSA_HOST, "ed-current", "EdDSA"),
+    ] {
+        let token = login(host, "test_jwt_user");
+        let header = token_header(&token);
+        assert_eq!(header["kid"], kid);
+        assert_eq!(header["alg"], alg);
+
+        // Either instance verifies the tokens of the other, as both list both keys
+        for host in [HS_HOST, EDDSA_HOST] {
+            let response = client_with_token(&token)
+                .get(format!("{}/me", host))
+                .send()
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let json: Value = response.json().unwrap();
+            assert_eq!(json["username"], "test_jwt_user");
+        }
+    }
+
+    // JWTs mean nothing to the session mode server
+    let token = login(HS_HOST, "test_jwt_user");
+    let response = client_with_token(&token)
+        .get(format!("{}/me", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    delete_test_user(user);
+}
+
+#[test]
+fn test_jwt_signed_with_listed_keys() {
+    start_servers();
+    let user = create_test_user("test_jwt_signed_user");
+
+    let hs = EncodingKey::from_secret(HS_SECRET.as_bytes());
+    let eddsa = EncodingKey::from_ed_pem(ED_PRIVATE_KEY.as_bytes()).unwrap();
+    for token in [
+        sign("hs-current", Algorithm::HS256, &hs, &user),
+        sign("ed-current", Algorithm::EdDSA, &eddsa, &user),
+    ] {
+        let response = client_with_token(&token)
+            .get(format!("{}/me", HS_HOST))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    delete_test_user(user);
+}
+
+#[test]
+fn test_jwt_rotated_out_key_rejected() {
+    start_servers();
+    let user = create_test_user("test_jwt_rotated_user");
+
+    // Same secret, but under a kid that is no longer configured
+    let hs = EncodingKey::from_secret(HS_SECRET.as_bytes());
+    let token = sign("hs-retired", Algorithm::HS256, &hs, &user);
+    let response = client_with_token(&token)
+        .get(format!("{}/me", HS_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // A listed kid doesn't help a token signed with another secret
+    let other = EncodingKey::from_secret(b"some-other-secret");
+    let token = sign("hs-current", Algorithm::HS256, &other, &user);
+    let response = client_with_token(&token)
+        .get(format!("{}/me", HS_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    delete_test_user(user);
+}
+
+#[test]
+fn test_jwt_logout() {
+    start_servers();
+    let user = create_test_user("test_jwt_logout_user");
+    let token = login(HS_HOST, "test_jwt_logout_user");
+    let other_token = login(HS_HOST, "test_jwt_logout_user");
+    let client = client_with_token(&token);
+
+    let response = client.post(format!("{}/logout", HS_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // The token's jti is denylisted on every instance, other tokens live on
+    for host in [HS_HOST, EDDSA_HOST] {
+        let response = client.get(format!("{}/me", host)).send().unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = client_with_token(&other_token)
+        .get(format!("{}/me", HS_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    delete_test_user(user);
+}
+
+#[test]
+fn test_jwt_suspension() {
+    start_servers();
+    let user = create_test_user("test_jwt_suspended_user");
+    let client = client_with_token(&login(HS_HOST, "test_jwt_suspended_user"));
+    let response = client.get(format!("{}/me", HS_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The session mode admin exists by now, see create_test_user
+    let admin_client = client_with_token(&login(HS_HOST, "test_admin"));
+    let response = admin_client
+        .post(format!("{}/users/{}/suspend", HS_HOST, user["id"]))
+        .json(&json!({ "reason": "Testing" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Every token issued to the user so far is denylisted
+    let response = client.get(format!("{}/me", HS_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = Client::new()
+        .post(format!("{}/login", HS_HOST))
+        .json(&json!({ "username": "test_jwt_suspended_user", "password": "1234" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    delete_test_user(user);
+}
@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use cr8s::mail::{Email, MailTransport, MemoryMailTransport};
+use cr8s::mail_queue::{self, QueueMailTransport};
+use diesel::{Connection, PgConnection};
+
+fn connect() -> PgConnection {
+    let database_url = std::env::var("DATABASE_URL").unwrap();
+    PgConnection::establish(&database_url).unwrap()
+}
+
+fn test_email(to: &str) -> Email {
+    Email {
+        from: "Cr8s <info@cr8s.com>".to_owned(),
+        to: to.to_owned(),
+        subject: "Queued".to_owned(),
+        html_body: "<p>Queued</p>".to_owned(),
+        text_body: Some("Queued".to_owned()),
+        list_unsubscribe: None,
+    }
+}
+
+/// Fails every email, permanently or not, and remembers who it failed.
+struct FailingMailTransport {
+    permanent: bool,
+    attempted: Arc<Mutex<Vec<String>>>,
+}
+
+impl MailTransport for FailingMailTransport {
+    fn send(&self, email: &Email) -> Result<(), Box<dyn Error>> {
+        self.attempted.lock().unwrap().push(email.to.to_owned());
+        match self.permanent {
+            true => Err(Box::new(lettre::address::AddressError::MissingParts)),
+            false => Err("Connection reset by peer".into()),
+        }
+    }
+}
+
+fn list_queue(status: &str) -> String {
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("mail-queue")
+        .arg("list")
+        .arg("--status")
+        .arg(status)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_mail_queue() {
+    let c = connect();
+    let queue = QueueMailTransport {
+        connection: connect(),
+    };
+
+    // Delivered on the first attempt.
+    queue.send(&test_email("queue-sent@cr8s.com")).unwrap();
+    let transport = MemoryMailTransport::default();
+    mail_queue::deliver_due(&c, &transport, 1000).unwrap();
+    let sent: Vec<_> = transport
+        .messages()
+        .into_iter()
+        .filter(|m| m.to == "queue-sent@cr8s.com")
+        .collect();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].text_body.as_deref(), Some("Queued"));
+
+    // A transient failure is retried later.
+    queue.send(&test_email("queue-retried@cr8s.com")).unwrap();
+    let attempted = Arc::new(Mutex::new(vec![]));
+    let transport = FailingMailTransport {
+        permanent: false,
+        attempted: attempted.clone(),
+    };
+    mail_queue::deliver_due(&c, &transport, 1000).unwrap();
+    assert!(attempted
+        .lock()
+        .unwrap()
+        .contains(&"queue-retried@cr8s.com".to_owned()));
+    let pending = list_queue("pending");
+    assert!(pending.contains("to queue-retried@cr8s.com \"Queued\" attempts: 1"));
+    assert!(pending.contains("Connection reset by peer"));
+
+    // Not due yet, so not attempted again right away.
+    let transport = MemoryMailTransport::default();
+    mail_queue::deliver_due(&c, &transport, 1000).unwrap();
+    assert!(transport
+        .messages()
+        .iter()
+        .all(|m| m.to != "queue-retried@cr8s.com"));
+
+    // A permanent failure goes straight to the dead letters.
+    queue.send(&test_email("queue-dead@cr8s.com")).unwrap();
+    let transport = FailingMailTransport {
+        permanent: true,
+        attempted: Arc::new(Mutex::new(vec![])),
+    };
+    mail_queue::deliver_due(&c, &transport, 1000).unwrap();
+    let dead = list_queue("dead");
+    assert!(dead.contains("to queue-dead@cr8s.com \"Queued\" attempts: 1"));
+}
+
+#[test]
+fn test_mail_queue_refuses_invalid_address() {
+    let queue = QueueMailTransport {
+        connection: connect(),
+    };
+    assert!(queue.send(&test_email("not an address")).is_err());
+}
+
+#[test]
+fn test_retry_delay_backs_off() {
+    assert!(mail_queue::retry_delay(1).num_seconds() >= 60);
+    assert!(mail_queue::retry_delay(1).num_seconds() <= 66);
+    assert!(mail_queue::retry_delay(3).num_seconds() >= 240);
+    assert!(mail_queue::retry_delay(30).num_seconds() <= 6 * 60 * 60 * 11 / 10);
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Mutex, Once};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::{
+    blocking::{Client, ClientBuilder},
+    header::{self, HeaderMap, HeaderValue},
+    redirect::Policy,
+    StatusCode, Url,
+};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::response::Redirect;
+use rocket::State;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+pub mod common;
+
+// The server's `ROCKET_OIDC` config (see docker-compose.yml) points here.
+static MOCK_ISSUER: &str = "http://127.0.0.1:8001";
+static MOCK_ISSUER_STARTED: Once = Once::new();
+
+struct PendingCode {
+    nonce: String,
+    code_challenge: String,
+}
+
+#[derive(Default)]
+struct MockIssuer {
+    codes: Mutex<HashMap<String, PendingCode>>,
+}
+
+#[rocket::get("/.well-known/openid-configuration")]
+fn discovery() -> Value {
+    json!({
+        "issuer": MOCK_ISSUER,
+        "authorization_endpoint": format!("{}/authorize", MOCK_ISSUER),
+        "token_endpoint": format!("{}/token", MOCK_ISSUER),
+        "jwks_uri": format!("{}/jwks", MOCK_ISSUER),
+    })
+}
+
+#[rocket::get("/jwks")]
+fn jwks() -> Value {
+    serde_json::from_str(include_str!("fixtures/oidc_mock_issuer.jwks.json")).unwrap()
+}
+
+#[rocket::get("/authorize?<redirect_uri>&<state>&<nonce>&<code_challenge>")]
+fn authorize(
+    issuer: &State<MockIssuer>,
+    redirect_uri: String,
+    state: String,
+    nonce: String,
+    code_challenge: String,
+) -> Redirect {
+    let code = format!("code-{}", state);
+    issuer.codes.lock().unwrap().insert(
+        code.clone(),
+        PendingCode {
+            nonce,
+            code_challenge,
+        },
+    );
+
+    let mut url = Url::parse(&redirect_uri).unwrap();
+    url.query_pairs_mut()
+        .append_pair("code", &code)
+        .append_pair("state", &state);
+    Redirect::to(url.to_string())
+}
+
+#[rocket::post("/token", data = "<form>")]
+fn token(
+    issuer: &State<MockIssuer>,
+    form: Form<HashMap<String, String>>,
+) -> Result<Value, Status> {
+    let field = |name: &str| form.get(name).map(String::as_str);
+    let pending = issuer
+        .codes
+        .lock()
+        .unwrap()
+        .remove(field("code").ok_or(Status::BadRequest)?)
+        .ok_or(Status::BadRequest)?;
+    let code_verifier = field("code_verifier").ok_or(Status::BadRequest)?;
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+    if field("grant_type") != Some("authorization_code")
+        || field("client_id") != Some("cr8s")
+        || field("client_secret") != Some("cr8s-secret")
+        || !field("redirect_uri").is_some_and(|uri| uri.ends_with("/auth/oidc/callback"))
+        || code_challenge != pending.code_challenge
+    {
+        return Err(Status::BadRequest);
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let claims = json!({
+        "iss": MOCK_ISSUER,
+        "aud": "cr8s",
+        "sub": "mock-subject-1",
+        "email": "test_oidc_editor@cr8s.com",
+        "email_verified": true,
+        "preferred_username": "test_oidc_editor",
+        "groups": ["cr8s-editors"],
+        "nonce": pending.nonce,
+        "iat": now,
+        "exp": now + 300,
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("mock-issuer-1".to_owned());
+    let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/oidc_mock_issuer.pem")).unwrap();
+
+    Ok(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "id_token": encode(&header, &claims, &key).unwrap(),
+    }))
+}
+
+fn start_mock_issuer() {
+    MOCK_ISSUER_STARTED.call_once(|| {
+        std::thread::spawn(|| {
+            let config = rocket::Config {
+                address: Ipv4Addr::LOCALHOST.into(),
+                port: 8001,
+                log_level: rocket::config::LogLevel::Off,
+                ..rocket::Config::debug_default()
+            };
+            let _ = rocket::execute(
+                rocket::custom(config)
+                    .manage(MockIssuer::default())
+                    .mount("/", rocket::routes![discovery, jwks, authorize, token])
+                    .launch(),
+            );
+        });
+
+        for _ in 0..50 {
+            if reqwest::blocking::get(format!("{}/jwks", MOCK_ISSUER)).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("Mock OIDC issuer did not start");
+    });
+}
+
+fn follow(client: &Client, url: &str) -> reqwest::blocking::Response {
+    let response = client.get(url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let location = response.headers()[header::LOCATION].to_str().unwrap().to_owned();
+    client.get(location).send().unwrap()
+}
+
+#[test]
+fn test_oidc_login() {
+    start_mock_issuer();
+    let client = ClientBuilder::new().redirect(Policy::none()).build().unwrap();
+
+    // Login redirects to the issuer with a PKCE challenge
+    let response = client
+        .get(format!("{}/auth/oidc/login", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let location = Url::parse(response.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+    assert!(location.as_str().starts_with(MOCK_ISSUER));
+    let params: HashMap<_, _> = location.query_pairs().into_owned().collect();
+    assert_eq!(params["code_challenge_method"], "S256");
+    assert_eq!(params["client_id"], "cr8s");
+
+    // The issuer sends the browser back to the callback, which issues a session
+    let response = follow(&client, location.as_str());
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let token = json["token"].as_str().unwrap();
+    assert_eq!(token.len(), 128);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(format!("Bearer {}", token).as_str()).unwrap(),
+    );
+    let client = ClientBuilder::new().default_headers(headers).build().unwrap();
+
+    let response = client.get(format!("{}/me", common::APP_HOST)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["username"], "test_oidc_editor");
+    assert_eq!(json["email"], "test_oidc_editor@cr8s.com");
+
+    // The cr8s-editors group maps onto the editor role
+    let response = client
+        .get(format!("{}/crates", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test]
+fn test_oidc_callback_with_unknown_state() {
+    start_mock_issuer();
+    let client = ClientBuilder::new().redirect(Policy::none()).build().unwrap();
+
+    let response = client
+        .get(format!(
+            "{}/auth/oidc/callback?code=foo&state=bar",
+            common::APP_HOST
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+
+use cr8s::rocket_routes::{openapi, v1};
+use reqwest::{blocking::Client, StatusCode};
+use serde_json::Value;
+
+pub mod common;
+
+fn get_spec() -> Value {
+    let response = Client::new()
+        .get(format!("{}/openapi.json", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().unwrap()
+}
+
+fn keys(value: &Value) -> BTreeSet<String> {
+    value.as_object().unwrap().keys().cloned().collect()
+}
+
+/// The schema's properties must be exactly what the API answers with.
+fn assert_schema_matches(spec: &Value, schema: &str, value: &Value) {
+    let schema = &spec["components"]["schemas"][schema];
+    assert_eq!(keys(&schema["properties"]), keys(value), "{}", schema);
+    for required in schema["required"].as_array().unwrap() {
+        assert!(value.get(required.as_str().unwrap()).is_some());
+    }
+}
+
+#[test]
+fn test_openapi_documents_every_route() {
+    let spec = get_spec();
+    assert_eq!(spec["openapi"], "3.1.0");
+    assert_eq!(spec["servers"][0]["url"], v1::PREFIX);
+
+    let routes = v1::routes();
+    for route in &routes {
+        let (path, parameters) = openapi::path_of(route);
+        let method = route.method.as_str().to_lowercase();
+        let operation = &spec["paths"][path.as_str()][method.as_str()];
+        assert!(
+            operation["summary"].is_string(),
+            "{} {} is not in the OpenAPI document",
+            method,
+            path
+        );
+        assert_eq!(
+            operation["parameters"].as_array().map_or(0, |p| p.len()),
+            parameters.len()
+        );
+        assert!(operation["responses"]["500"].is_object());
+    }
+
+    // Nor does the document describe routes that are gone.
+    let operations: usize = spec["paths"]
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|item| item.as_object().unwrap().len())
+        .sum();
+    assert_eq!(operations, routes.len());
+
+    let login = &spec["paths"]["/login"]["post"];
+    assert_eq!(login["security"], serde_json::json!([]));
+    assert_eq!(
+        login["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/Credentials"
+    );
+    assert_eq!(
+        spec["components"]["securitySchemes"]["bearerAuth"]["scheme"],
+        "bearer"
+    );
+}
+
+#[test]
+fn test_openapi_schemas_match_responses() {
+    let spec = get_spec();
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    assert_schema_matches(&spec, "Rustacean", &rustacean);
+    assert_schema_matches(&spec, "Crate", &a_crate);
+    // `?fields=` picks from the same fields.
+    assert_eq!(
+        keys(&spec["components"]["schemas"]["Crate"]["properties"]),
+        v1::Crate::FIELDS.into_iter().map(String::from).collect()
+    );
+    assert_eq!(
+        keys(&spec["components"]["schemas"]["Rustacean"]["properties"]),
+        v1::Rustacean::FIELDS.into_iter().map(String::from).collect()
+    );
+    assert_eq!(
+        keys(&spec["components"]["schemas"]["NewCrate"]["properties"]),
+        ["code", "description", "name", "rustacean_id", "version"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    );
+    assert_eq!(
+        keys(&spec["components"]["schemas"]["NewRustacean"]["properties"]),
+        ["email", "name"].into_iter().map(String::from).collect()
+    );
+    assert_eq!(
+        keys(&spec["components"]["schemas"]["Credentials"]["properties"]),
+        ["password", "username"].into_iter().map(String::from).collect()
+    );
+
+    // Cleanup
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_docs_ui() {
+    let response = Client::new()
+        .get(format!("{}/docs", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().unwrap();
+    assert!(page.contains("url: \"/openapi.json\""));
+    assert!(page.contains("src=\"/docs/assets/swagger-ui-bundle.js\""));
+
+    // Served by the app, not a CDN.
+    let response = Client::new()
+        .get(format!("{}/docs/assets/swagger-ui-bundle.js", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -1,4 +1,10 @@
-use reqwest::{blocking::Client, header::AUTHORIZATION, StatusCode};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::{
+    blocking::Client,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, IF_MATCH},
+    StatusCode,
+};
 use serde_json::{json, Value};
 
 pub mod common;
@@ -172,3 +178,366 @@ fn test_delete_rustacean() {
 
     assert_eq!(response.status(), StatusCode::NO_CONTENT);
 }
+
+#[test]
+fn test_create_rustacean_with_invalid_fields() {
+    let client = common::get_client_with_logged_in_admin();
+    let response = client
+        .post("http://127.0.0.1:8000/rustaceans")
+        .json(&json!({
+            "name": "",
+            "email": "not an email",
+        }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(
+        json,
+        json!({
+            "errors": {
+                "email": ["must be an email address"],
+                "name": ["must be 1 to 128 characters"],
+            }
+        })
+    );
+}
+
+#[test]
+fn test_create_rustacean_with_idempotency_key() {
+    let client = common::get_client_with_logged_in_admin();
+    let key = format!(
+        "test-{}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+    let body = json!({
+        "name": "Foo bar",
+        "email": format!("{}@bar.com", key),
+    });
+
+    let response = client
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .header("Idempotency-Key", &key)
+        .json(&body)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let rustacean: Value = response.json().unwrap();
+
+    // A retry gets the same rustacean rather than a second one.
+    let response = client
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .header("Idempotency-Key", &key)
+        .json(&body)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.json::<Value>().unwrap(), rustacean);
+
+    let mut other_body = body.clone();
+    other_body["name"] = json!("Fooz");
+    let response = client
+        .post(format!("{}/rustaceans", common::APP_HOST))
+        .header("Idempotency-Key", &key)
+        .json(&other_body)
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_patch_rustacean() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .patch(format!(
+            "http://127.0.0.1:8000/rustaceans/{}",
+            rustacean["id"]
+        ))
+        .header(CONTENT_TYPE, "application/merge-patch+json")
+        .body(json!({ "name": "FooZ" }).to_string())
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(
+        json,
+        json!({
+            "id": rustacean["id"],
+            "email": rustacean["email"],
+            "name": "FooZ",
+            "created_at": rustacean["created_at"]
+        })
+    );
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_patch_rustacean_with_stale_etag() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .patch(format!(
+            "http://127.0.0.1:8000/rustaceans/{}",
+            rustacean["id"]
+        ))
+        .header(CONTENT_TYPE, "application/merge-patch+json")
+        .header(IF_MATCH, "\"0\"")
+        .body(json!({ "name": "FooZ" }).to_string())
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_update_and_delete_missing_rustacean() {
+    let client = common::get_client_with_logged_in_admin();
+
+    let response = client
+        .put("http://127.0.0.1:8000/rustaceans/999999")
+        .json(&json!({
+            "email": "fooz@bar.com",
+            "name": "FooZ",
+        }))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .delete("http://127.0.0.1:8000/rustaceans/999999")
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_delete_rustacean_twice() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+
+    let response = client
+        .delete(format!(
+            "http://127.0.0.1:8000/rustaceans/{}",
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .delete(format!(
+            "http://127.0.0.1:8000/rustaceans/{}",
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_delete_rustacean_with_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .delete(format!(
+            "http://127.0.0.1:8000/rustaceans/{}",
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_view_rustacean_with_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:8000/rustaceans/{}?include=crates&fields=name",
+            rustacean["id"]
+        ))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(
+        json,
+        json!({
+            "name": rustacean["name"],
+            "crates": [a_crate],
+        })
+    );
+
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_rustaceans_in_bulk() {
+    let client = common::get_client_with_logged_in_admin();
+    let response = client
+        .post("http://127.0.0.1:8000/rustaceans/bulk")
+        .json(&json!([
+            { "name": "Foo", "email": "foo@bar.com" },
+            { "name": "Bar", "email": "bar@bar.com" },
+        ]))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let json: Value = response.json().unwrap();
+    let results = json["results"].as_array().unwrap();
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["data"]["name"], "Foo");
+    assert_eq!(results[1]["data"]["name"], "Bar");
+
+    for result in results {
+        common::delete_test_rustacean(&client, result["data"].clone());
+    }
+}
+
+#[test]
+fn test_update_rustaceans_in_bulk() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let response = client
+        .put(format!("{}/rustaceans/bulk", common::APP_HOST))
+        .json(&json!([
+            { "id": rustacean["id"], "name": "Fooz", "email": "fooz@bar.com" },
+        ]))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["results"][0]["status"], 200);
+    assert_eq!(json["results"][0]["data"]["name"], "Fooz");
+
+    let response = client
+        .put(format!("{}/rustaceans/bulk?mode=best_effort", common::APP_HOST))
+        .json(&json!([
+            { "id": rustacean["id"], "name": "Foo", "email": "foo@bar.com" },
+            { "id": 999999, "name": "Bar", "email": "bar@bar.com" },
+        ]))
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["results"][0]["status"], 200);
+    assert_eq!(json["results"][1]["status"], 404);
+
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_export_and_import_rustaceans() {
+    let client = common::get_client_with_logged_in_admin();
+    let email = format!(
+        "import-{}@bar.com",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    );
+    let csv = format!("name,email\nFoo,{}\n", email);
+    let import = |query: &str| {
+        client
+            .post(format!("{}/rustaceans/import{}", common::APP_HOST, query))
+            .header(CONTENT_TYPE, "text/csv")
+            .body(csv.clone())
+            .send()
+            .unwrap()
+    };
+
+    let response = import("?dry_run=true");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["applied"], false);
+    assert_eq!(json["lines"][0]["action"], "create");
+
+    let response = import("");
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["applied"], true);
+    assert_eq!(json["lines"][0]["action"], "create");
+
+    // Matched by email the second time.
+    let response = import("");
+    let json: Value = response.json().unwrap();
+
+    assert_eq!(json["lines"][0]["action"], "unchanged");
+
+    let id = json["lines"][0]["id"].clone();
+    let response = client
+        .get(format!("{}/rustaceans", common::APP_HOST))
+        .header(ACCEPT, "application/x-ndjson")
+        .send()
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+
+    let rustaceans: Vec<Value> = response
+        .text()
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let rustacean = rustaceans
+        .into_iter()
+        .find(|rustacean| rustacean["id"] == id)
+        .unwrap();
+
+    assert_eq!(rustacean["name"], "Foo");
+    assert_eq!(rustacean["email"], email.as_str());
+
+    common::delete_test_rustacean(&client, rustacean);
+}
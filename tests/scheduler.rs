@@ -0,0 +1,55 @@
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+fn job_history() -> String {
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--bin")
+        .arg("cli")
+        .arg("scheduler")
+        .arg("history")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn has_succeeded(history: &str, job: &str) -> bool {
+    history
+        .lines()
+        .any(|line| line.contains(&format!(" {} ", job)) && line.ends_with(" succeeded"))
+}
+
+#[test]
+fn test_scheduler_records_job_runs() {
+    let config = std::env::temp_dir().join("cr8s-test-scheduler.toml");
+    std::fs::write(
+        &config,
+        "[[jobs]]\nname = \"test-session-cleanup\"\nschedule = \"* * * * * *\"\ntask = \"session-cleanup\"\n",
+    )
+    .unwrap();
+
+    // Not through `cargo run`, killing cargo would leave the scheduler running.
+    let mut scheduler = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg("scheduler")
+        .arg("run")
+        .env("SCHEDULER_CONFIG", &config)
+        .spawn()
+        .unwrap();
+
+    let mut history = String::new();
+    for _ in 0..30 {
+        thread::sleep(Duration::from_secs(1));
+        history = job_history();
+        if has_succeeded(&history, "test-session-cleanup") {
+            break;
+        }
+    }
+    scheduler.kill().unwrap();
+    scheduler.wait().unwrap();
+    std::fs::remove_file(config).unwrap();
+
+    println!("{}", history);
+    assert!(has_succeeded(&history, "test-session-cleanup"));
+}
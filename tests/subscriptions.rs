@@ -0,0 +1,143 @@
+use cr8s::mail::MemoryMailTransport;
+use reqwest::{blocking::Client, StatusCode};
+use serde_json::{json, Value};
+
+pub mod common;
+
+#[test]
+fn test_manage_own_subscription() {
+    let client = common::get_client_with_logged_in_viewer();
+    let response = client
+        .post(format!("{}/digest/subscriptions", common::APP_HOST))
+        .json(&json!({"frequency": "weekly", "keyword": "async"}))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let subscription: Value = response.json().unwrap();
+    assert!(subscription["user_id"].is_number());
+    assert_eq!(subscription["frequency"], "weekly");
+
+    let response = client
+        .put(format!(
+            "{}/digest/subscriptions/{}",
+            common::APP_HOST,
+            subscription["id"]
+        ))
+        .json(&json!({"frequency": "daily", "keyword": null, "owner_id": null}))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let updated: Value = response.json().unwrap();
+    assert_eq!(updated["frequency"], "daily");
+    assert_eq!(updated["keyword"], Value::Null);
+
+    let response = client
+        .get(format!("{}/digest/subscriptions", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let subscriptions: Value = response.json().unwrap();
+    assert!(subscriptions
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|s| s["user_id"] == subscription["user_id"]));
+
+    let response = client
+        .delete(format!(
+            "{}/digest/subscriptions/{}",
+            common::APP_HOST,
+            subscription["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn test_viewer_cannot_subscribe_rustacean() {
+    let admin_client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&admin_client);
+
+    let client = common::get_client_with_logged_in_viewer();
+    let response = client
+        .post(format!("{}/digest/subscriptions", common::APP_HOST))
+        .json(&json!({"rustacean_id": rustacean["id"]}))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Cleanup
+    common::delete_test_rustacean(&admin_client, rustacean);
+}
+
+#[test]
+fn test_send_all_digests_and_unsubscribe() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+
+    let response = client
+        .post(format!("{}/digest/subscriptions", common::APP_HOST))
+        .json(&json!({
+            "rustacean_id": rustacean["id"],
+            "owner_id": rustacean["id"],
+            "keyword": "FOO BAR",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let subscription: Value = response.json().unwrap();
+
+    let transport = MemoryMailTransport::default();
+    cr8s::commands::send_all_digests_with(Box::new(transport.clone()));
+
+    let messages: Vec<_> = transport
+        .messages()
+        .into_iter()
+        .filter(|m| m.to == rustacean["email"].as_str().unwrap())
+        .collect();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].html_body.contains(a_crate["code"].as_str().unwrap()));
+    let list_unsubscribe = messages[0].list_unsubscribe.as_ref().unwrap();
+    let unsubscribe_url = &list_unsubscribe[1..list_unsubscribe.find('>').unwrap()];
+    assert!(unsubscribe_url.contains("/digest/unsubscribe?token="));
+
+    // Not due again until tomorrow.
+    let transport = MemoryMailTransport::default();
+    cr8s::commands::send_all_digests_with(Box::new(transport.clone()));
+    assert!(transport
+        .messages()
+        .iter()
+        .all(|m| m.to != rustacean["email"].as_str().unwrap()));
+
+    let response = Client::new().post(unsubscribe_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/digest/subscriptions", common::APP_HOST))
+        .send()
+        .unwrap();
+    let subscriptions: Value = response.json().unwrap();
+    assert!(subscriptions
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|s| s["id"] != subscription["id"]));
+
+    // Cleanup
+    common::delete_test_crate(&client, a_crate);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_unsubscribe_with_forged_token() {
+    let response = Client::new()
+        .post(format!(
+            "{}/digest/unsubscribe?token=1.bm90LWEtc2lnbmF0dXJl",
+            common::APP_HOST
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,179 @@
+use reqwest::{blocking::Client, header::AUTHORIZATION, StatusCode};
+use serde_json::{json, Value};
+
+pub mod common;
+
+fn create_test_user(client: &Client, username: &str) -> Value {
+    // A leftover from an earlier run would make the create below conflict
+    let response = client
+        .get(format!("{}/users", common::APP_HOST))
+        .send()
+        .unwrap();
+    let users: Value = response.json().unwrap();
+    for user in users.as_array().unwrap() {
+        if user["username"] == username {
+            client
+                .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+                .send()
+                .unwrap();
+        }
+    }
+
+    let response = client
+        .post(format!("{}/users", common::APP_HOST))
+        .json(&json!({
+            "username": username,
+            "password": "1234",
+            "roles": ["viewer"],
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    response.json().unwrap()
+}
+
+#[test]
+fn test_get_users() {
+    let client = common::get_client_with_logged_in_admin();
+    let user = create_test_user(&client, "test_listed_user");
+
+    let response = client
+        .get(format!("{}/users", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let users: Value = response.json().unwrap();
+    assert!(users.as_array().unwrap().contains(&user));
+    assert_eq!(user["roles"][0]["code"], "viewer");
+    assert!(user.get("password").is_none());
+
+    // Cleanup
+    client
+        .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+}
+
+#[test]
+fn test_users_require_admin() {
+    let client = common::get_client_with_logged_in_editor();
+
+    let response = client
+        .get(format!("{}/users", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_grant_and_revoke_role() {
+    let client = common::get_client_with_logged_in_admin();
+    let user = create_test_user(&client, "test_promoted_user");
+
+    let response = client
+        .post(format!("{}/users/{}/roles", common::APP_HOST, user["id"]))
+        .json(&json!({ "role": "editor" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let codes: Vec<&str> = json["roles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["code"].as_str().unwrap())
+        .collect();
+    assert!(codes.contains(&"viewer"));
+    assert!(codes.contains(&"editor"));
+
+    let response = client
+        .delete(format!("{}/users/{}/roles/viewer", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["roles"].as_array().unwrap().len(), 1);
+    assert_eq!(json["roles"][0]["code"], "editor");
+
+    // Cleanup
+    client
+        .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+}
+
+#[test]
+fn test_delete_user() {
+    let client = common::get_client_with_logged_in_admin();
+    let user = create_test_user(&client, "test_deleted_user");
+
+    let response = client
+        .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/users/{}", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_suspend_and_reinstate_user() {
+    let client = common::get_client_with_logged_in_admin();
+    let user = create_test_user(&client, "test_suspended_user");
+    let login = json!({ "username": "test_suspended_user", "password": "1234" });
+
+    let response = Client::new()
+        .post(format!("{}/login", common::APP_HOST))
+        .json(&login)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    let token = json["token"].as_str().unwrap().to_owned();
+
+    let response = client
+        .post(format!("{}/users/{}/suspend", common::APP_HOST, user["id"]))
+        .json(&json!({ "reason": "Testing" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert!(json["disabled_at"].is_string());
+    assert_eq!(json["disabled_reason"], "Testing");
+
+    // Existing sessions are revoked and new logins refused
+    let response = Client::new()
+        .get(format!("{}/me", common::APP_HOST))
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = Client::new()
+        .post(format!("{}/login", common::APP_HOST))
+        .json(&login)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .post(format!("{}/users/{}/reinstate", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = Client::new()
+        .post(format!("{}/login", common::APP_HOST))
+        .json(&login)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Cleanup
+    client
+        .delete(format!("{}/users/{}", common::APP_HOST, user["id"]))
+        .send()
+        .unwrap();
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use reqwest::{blocking::Client, StatusCode};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+pub mod common;
+
+struct Received {
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+/// A bare HTTP endpoint that answers every request with 200 and hands it over.
+fn start_receiver() -> (String, Receiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = HashMap::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(": ") {
+                    headers.insert(name.to_lowercase(), value.to_owned());
+                }
+            }
+            let length = headers
+                .get("content-length")
+                .map_or(0, |l| l.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let body = String::from_utf8(body).unwrap();
+            if sender.send(Received { headers, body }).is_err() {
+                return;
+            }
+        }
+    });
+
+    (url, receiver)
+}
+
+/// Waits for the first request for `event`, skipping what other tests cause.
+fn receive(receiver: &Receiver<Received>, event: &str) -> Received {
+    loop {
+        let received = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        if received.headers["x-cr8s-event"] == event {
+            return received;
+        }
+    }
+}
+
+fn assert_signed(received: &Received, secret: &str) {
+    let timestamp = &received.headers["x-cr8s-timestamp"];
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, received.body).as_bytes());
+    let expected: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(
+        received.headers["x-cr8s-signature"],
+        format!("sha256={}", expected)
+    );
+}
+
+fn create_webhook(client: &Client, url: &str, events: Value) -> Value {
+    let response = client
+        .post(format!("{}/webhooks", common::APP_HOST))
+        .json(&json!({
+            "url": url,
+            "secret": "s3cret",
+            "events": events,
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    response.json().unwrap()
+}
+
+fn delete_webhook(client: &Client, webhook: Value) {
+    let response = client
+        .delete(format!("{}/webhooks/{}", common::APP_HOST, webhook["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+fn work_webhooks() {
+    let output = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg("webhooks")
+        .arg("work")
+        .arg("--once")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_ping_webhook() {
+    let client = common::get_client_with_logged_in_admin();
+    let (url, receiver) = start_receiver();
+    let webhook = create_webhook(&client, &url, json!(["rustacean.*"]));
+    assert!(webhook.get("secret").is_none());
+
+    let response = client
+        .post(format!("{}/webhooks/{}/ping", common::APP_HOST, webhook["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let delivery: Value = response.json().unwrap();
+    assert_eq!(delivery["event"], "ping");
+    assert_eq!(delivery["status"], "sent");
+    assert_eq!(delivery["response_status"], 200);
+
+    let received = receive(&receiver, "ping");
+    assert_signed(&received, "s3cret");
+    let payload: Value = serde_json::from_str(&received.body).unwrap();
+    assert_eq!(payload["data"]["webhook_id"], webhook["id"]);
+
+    let response = client
+        .get(format!(
+            "{}/webhooks/{}/deliveries",
+            common::APP_HOST,
+            webhook["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let deliveries: Value = response.json().unwrap();
+    assert_eq!(deliveries.as_array().unwrap().len(), 1);
+
+    delete_webhook(&client, webhook);
+}
+
+#[test]
+fn test_crate_events_are_delivered() {
+    let client = common::get_client_with_logged_in_admin();
+    let (url, receiver) = start_receiver();
+    let webhook = create_webhook(&client, &url, json!(["crate.created", "crate.deleted"]));
+
+    let rustacean = common::create_test_rustacean(&client);
+    let a_crate = common::create_test_crate(&client, &rustacean);
+    work_webhooks();
+
+    let received = loop {
+        let received = receive(&receiver, "crate.created");
+        let payload: Value = serde_json::from_str(&received.body).unwrap();
+        if payload["data"]["id"] == a_crate["id"] {
+            break received;
+        }
+    };
+    assert_signed(&received, "s3cret");
+    assert!(received.headers.contains_key("x-cr8s-delivery"));
+
+    let crate_id = a_crate["id"].clone();
+    common::delete_test_crate(&client, a_crate);
+    work_webhooks();
+    loop {
+        let received = receive(&receiver, "crate.deleted");
+        let payload: Value = serde_json::from_str(&received.body).unwrap();
+        if payload["data"]["id"] == crate_id {
+            break;
+        }
+    }
+
+    // Cleanup
+    delete_webhook(&client, webhook);
+    common::delete_test_rustacean(&client, rustacean);
+}
+
+#[test]
+fn test_create_webhook_with_unknown_event() {
+    let client = common::get_client_with_logged_in_admin();
+    let response = client
+        .post(format!("{}/webhooks", common::APP_HOST))
+        .json(&json!({
+            "url": "http://127.0.0.1:9/hook",
+            "secret": "s3cret",
+            "events": ["crate.published"],
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let client = common::get_client_with_logged_in_editor();
+    let response = client
+        .get(format!("{}/webhooks", common::APP_HOST))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}